@@ -1,28 +1,60 @@
+use std::io::{self, IsTerminal, Write};
 use std::net::IpAddr;
+use std::num::NonZeroUsize;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
 
-use clap::{Parser, Subcommand};
+use chrono::{DateTime, Days, NaiveTime, Utc};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use indexmap::IndexMap;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
 use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::json;
 use tokio::spawn;
 use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+use tracing_subscriber::EnvFilter;
 use url::Url;
 
-use streamrr::record::{RecordError, RecordOptions};
-use streamrr::replay::ReplayError;
+use streamrr::record::{
+    AuthSource, CommandAuthSource, EndpointAuthSource, EventHook, PlaylistPosition,
+    PlaylistProgress, RecordError, RecordOptions, RecordProgress,
+};
+use streamrr::replay::{
+    ClockStart, DvrOptions, ReplayError, ReplayOptions, ReplayServer, SessionMode,
+    SimulateLiveOptions, TlsOptions,
+};
 use streamrr::shared::{MediaSelect, VariantSelect, VariantSelectOptions, abort_on_ctrlc};
 
 /// Record and replay HLS streams.
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 #[command(propagate_version = true)]
-#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
     #[command(subcommand)]
     command: Option<CliCommand>,
     /// Print license information of this software.
-    #[arg(long)]
+    #[arg(long, exclusive = true)]
     license: bool,
+    /// The minimum level of log messages to print, or a list of filter directives.
+    ///
+    /// For example: "debug" or "streamrr=debug,warn".
+    #[arg(long, global = true, value_name = "LEVEL", default_value = "info")]
+    log_level: String,
+    /// The format of log messages.
+    #[arg(long, global = true, value_name = "FORMAT", default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, ValueEnum)]
+enum LogFormat {
+    /// Human-readable text.
+    Text,
+    /// One JSON object per line.
+    Json,
 }
 
 #[derive(Parser)]
@@ -33,6 +65,7 @@ struct CliRequired {
 }
 
 #[derive(Subcommand)]
+#[allow(clippy::large_enum_variant)] // only constructed once
 enum CliCommand {
     /// Record a HLS VOD or live stream.
     Record {
@@ -59,20 +92,24 @@ enum CliCommand {
         /// Cannot be used when --variant is set.
         #[arg(short = 'b', long, conflicts_with = "variant")]
         bandwidth: Option<u64>,
-        /// The start time of the first segment to record, in seconds.
+        /// The start time of the first segment to record, in seconds or as a date and time.
         ///
         /// - If positive, the start time counts from the start of the first media playlist.
         /// - If negative, the start time counts from the end of the first media playlist.
+        /// - If a date and time (e.g. "2026-10-18T20:00:00Z"), the recording starts at the segment
+        ///   with that #EXT-X-PROGRAM-DATE-TIME, waiting for it to appear if necessary.
         /// - If unset, the recording starts at the first segment of the first media playlist.
         #[arg(long, allow_hyphen_values = true, verbatim_doc_comment)]
-        start: Option<f32>,
-        /// The end time of the first segment to record, in seconds.
+        start: Option<PlaylistPosition>,
+        /// The end time of the last segment to record, in seconds or as a date and time.
         ///
         /// - If positive, the end time counts from the start of the first media playlist.
         /// - If negative, the end time counts from the end of the first media playlist.
+        /// - If a date and time (e.g. "2026-10-18T22:15:00Z"), the recording ends at the segment
+        ///   with that #EXT-X-PROGRAM-DATE-TIME, waiting for it to appear if necessary.
         /// - If unset, the recording stops at the last segment of the last media playlist.
         #[arg(long, allow_hyphen_values = true, verbatim_doc_comment)]
-        end: Option<f32>,
+        end: Option<PlaylistPosition>,
         /// Custom HTTP header to send with all requests.
         ///
         /// Can be specified multiple times. Format: "Name: Value"
@@ -85,24 +122,225 @@ enum CliCommand {
         /// then all segments would be written to the same `segment` file.
         #[arg(long)]
         keep_names: bool,
+        /// The wall-clock time at which to start recording.
+        ///
+        /// Either a date and time (e.g. "2026-10-18T20:00:00Z"), or a time of day in UTC
+        /// (e.g. "20:00"), which refers to the next occurrence of that time.
+        #[arg(long, value_name = "TIME", value_parser = parse_wall_clock_time)]
+        start_at: Option<WallClockTime>,
+        /// The wall-clock time at which to stop recording.
+        ///
+        /// Uses the same format as --start-at. A time of day refers to
+        /// the next occurrence of that time after the recording has started.
+        #[arg(long, value_name = "TIME", value_parser = parse_wall_clock_time)]
+        stop_at: Option<WallClockTime>,
+        /// The maximum duration of the recording, e.g. "45m" or "1h 30m".
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        duration: Option<Duration>,
+        /// The maximum number of segments to record for each media playlist.
+        #[arg(long, value_name = "COUNT", value_parser = clap::value_parser!(u64).range(1..))]
+        max_segments: Option<u64>,
+        /// The maximum size of the recording on disk, e.g. "500MB" or "2GB".
+        ///
+        /// Once reached, no new segments are downloaded and all playlists are ended.
+        #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+        max_size: Option<u64>,
+        /// Only keep the last part of a live recording, e.g. "30m".
+        ///
+        /// Older playlists, segments, keys and maps are deleted from the recording,
+        /// like the DVR window of a live stream.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        rolling_window: Option<Duration>,
+        /// Refresh live playlists at this fixed interval, e.g. "500ms".
+        ///
+        /// By default, a playlist is refreshed after the duration of its last segment,
+        /// or after half its target duration if it did not change, as recommended by the HLS specification.
+        /// Use a short interval to catch every update of the origin.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        poll_interval: Option<Duration>,
+        /// The maximum number of concurrent downloads for each media playlist.
+        #[arg(long, value_name = "COUNT", default_value = "4")]
+        concurrency: NonZeroUsize,
+        /// The maximum number of concurrent connections for the whole recording.
+        #[arg(long, value_name = "COUNT")]
+        max_connections: Option<NonZeroUsize>,
+        /// The maximum number of concurrent connections to a single host.
+        #[arg(long, value_name = "COUNT")]
+        max_connections_per_host: Option<NonZeroUsize>,
+        /// The URL of a proxy to use for all requests.
+        ///
+        /// Supports HTTP, HTTPS and SOCKS proxies, e.g. "socks5h://localhost:1080".
+        #[arg(long, value_name = "URL")]
+        proxy: Option<Url>,
+        /// Trust the root CA certificates in the given PEM file.
+        ///
+        /// Can be repeated to trust certificates from multiple files.
+        #[arg(long = "ca-cert", value_name = "FILE")]
+        ca_certs: Vec<PathBuf>,
+        /// Authenticate with the client certificate in the given PEM file (mutual TLS).
+        ///
+        /// The file must also contain the private key, unless --client-key is given.
+        #[arg(long, value_name = "FILE")]
+        client_cert: Option<PathBuf>,
+        /// The private key of the client certificate, as a PEM file.
+        #[arg(long, value_name = "FILE", requires = "client_cert")]
+        client_key: Option<PathBuf>,
+        /// Do not verify TLS certificates. This is dangerous!
+        #[arg(short = 'k', long)]
+        insecure: bool,
+        /// The timeout for connecting to a server, e.g. "10s".
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        connect_timeout: Option<Duration>,
+        /// The timeout for reading a response, e.g. "30s".
+        ///
+        /// The timeout is reset whenever some data is received.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        read_timeout: Option<Duration>,
+        /// The User-Agent header to send with every request.
+        #[arg(short = 'A', long, value_name = "USER_AGENT")]
+        user_agent: Option<String>,
+        /// Run this command to get authentication parameters for signed URLs.
+        ///
+        /// The command must print a query string (e.g. "token=abc") or a JSON object
+        /// (e.g. {"token": "abc"}). These parameters are added to the query of every request URL,
+        /// replacing existing parameters with the same name. The command is run again
+        /// whenever a request is rejected with 401 or 403, or when --auth-refresh has passed.
+        #[arg(long, value_name = "COMMAND", conflicts_with = "auth_url")]
+        auth_command: Option<String>,
+        /// Request authentication parameters for signed URLs from this token endpoint.
+        ///
+        /// The response must be a query string or a JSON object, as with --auth-command.
+        #[arg(long, value_name = "URL")]
+        auth_url: Option<Url>,
+        /// How often to refresh the authentication parameters, e.g. "5m".
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration)]
+        auth_refresh: Option<Duration>,
+        /// How long to wait for --auth-command or --auth-url before giving up.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "30s")]
+        auth_timeout: Duration,
+        /// Send the cookies from this file.
+        ///
+        /// Supports the Netscape cookies.txt format (as exported by browsers and curl),
+        /// and the JSON format written by --save-cookies.
+        #[arg(long, value_name = "FILE")]
+        cookies: Option<PathBuf>,
+        /// Save all cookies to cookies.json in the recording directory when the recording stops.
+        #[arg(long)]
+        save_cookies: bool,
+        /// POST every recording event as JSON to this URL,
+        /// e.g. new playlists, discontinuities, date ranges and download errors.
+        ///
+        /// Can be repeated to send the events to multiple URLs.
+        #[arg(long = "webhook", value_name = "URL")]
+        webhooks: Vec<Url>,
+        /// Run this command for every recording event.
+        ///
+        /// The event is written as JSON to its stdin,
+        /// and its type is set in the STREAMRR_EVENT environment variable.
+        #[arg(long, value_name = "COMMAND")]
+        on_event: Option<String>,
+        /// Show the live progress of every recorded playlist.
+        ///
+        /// Combine with --log-level=warn to hide the log messages for every playlist refresh.
+        #[arg(long)]
+        progress: bool,
     },
-    /// Replay a HLS VOD or live stream.
-    Replay {
-        /// The directory path of the recording of an HLS stream created by record.
+    /// Rebuild a damaged or missing recording.json from the playlist files of a recording.
+    Recover {
+        /// The directory path of the recording.
         #[arg(value_name = "PATH")]
         recording_path: PathBuf,
+    },
+    /// Replay a HLS VOD or live stream.
+    Replay {
+        /// The directory path(s) of the recording(s) of an HLS stream created by record.
+        ///
+        /// A single recording is served at the root of the server.
+        /// Multiple recordings, or a directory containing recordings,
+        /// are served under /rec/<name>/ and listed at /rec/.
+        #[arg(value_name = "PATH", required = true)]
+        recording_paths: Vec<PathBuf>,
         /// The address on which to run the server.
         #[arg(short = 'a', long, value_name = "ADDRESS", default_value_t = IpAddr::from([127, 0, 0, 1]))]
         address: IpAddr,
         /// The port on which to run the server.
+        ///
+        /// Use 0 to pick any free port. Once the server is listening, a JSON line
+        /// with its actual address is printed on stdout.
         #[arg(short = 'p', long, value_name = "PORT", default_value_t = 8080)]
         port: u16,
+        /// Add an extra HTTP response header to every response,
+        /// replacing the recorded header with the same name.
+        ///
+        /// Can be repeated to add multiple headers.
+        #[arg(short = 'H', long = "header", value_name = "HEADER", value_parser = parse_header)]
+        headers: Vec<(HeaderName, HeaderValue)>,
+        /// Remove an HTTP response header from every response, e.g. "Cache-Control".
+        ///
+        /// Can be repeated to remove multiple headers.
+        #[arg(long = "drop-header", value_name = "NAME")]
+        drop_headers: Vec<HeaderName>,
+        /// Serve over HTTPS (with HTTP/2) using the certificate chain in this PEM file.
+        #[arg(long, value_name = "FILE", requires = "tls_key")]
+        tls_cert: Option<PathBuf>,
+        /// The private key of the certificate given with --tls-cert, as a PEM file.
+        #[arg(long, value_name = "FILE", requires = "tls_cert")]
+        tls_key: Option<PathBuf>,
+        /// Serve over HTTPS (with HTTP/2) using a newly generated self-signed certificate.
+        #[arg(long, conflicts_with = "tls_cert")]
+        tls_self_signed: bool,
+        /// How to keep track of the start time of each client.
+        #[arg(long, value_name = "MODE", default_value = "query")]
+        session: SessionMode,
+        /// When to start the shared clock of the global session.
+        ///
+        /// With "manual", the clock is started with a POST request to /control/clock/start.
+        #[arg(long, value_name = "WHEN", default_value = "server")]
+        clock_start: ClockStart,
+        /// Shift all program date times and date ranges to the wall clock of the replay.
+        #[arg(long)]
+        rewrite_pdt: bool,
+        /// Replay a VOD recording as a live stream, with a sliding window that advances
+        /// by the segment durations.
+        ///
+        /// Cannot be used with recordings of live streams.
+        #[arg(long)]
+        simulate_live: bool,
+        /// The duration of the live window when using --simulate-live.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, default_value = "30s", requires = "simulate_live")]
+        live_window: Duration,
+        /// Keep all segments in an EVENT playlist when using --simulate-live,
+        /// instead of a sliding window.
+        #[arg(long, requires = "simulate_live")]
+        live_event: bool,
+        /// End the playlist with #EXT-X-ENDLIST when all segments are available
+        /// when using --simulate-live.
+        #[arg(long, requires = "simulate_live")]
+        live_endlist: bool,
+        /// Replay a live recording with a DVR window of this duration,
+        /// using all segments recorded so far.
+        #[arg(long, value_name = "DURATION", value_parser = humantime::parse_duration, conflicts_with = "simulate_live")]
+        dvr_window: Option<Duration>,
+        /// Replay a live recording as an EVENT playlist,
+        /// with all segments since the start of the recording.
+        #[arg(long, conflicts_with_all = ["simulate_live", "dvr_window"])]
+        dvr_event: bool,
     },
 }
 
 #[tokio::main]
 async fn main() {
     let args = Cli::parse();
+    if args.license && args.command.is_some() {
+        Cli::command()
+            .error(
+                ErrorKind::ArgumentConflict,
+                "the argument '--license' cannot be used with a subcommand",
+            )
+            .exit()
+    }
+    let multi_progress = MultiProgress::new();
+    init_logging(&args.log_level, args.log_format, &multi_progress);
     let Some(command) = args.command else {
         if args.license {
             println!(include_str!("../LICENSE.md"));
@@ -129,12 +367,52 @@ async fn main() {
             end,
             headers,
             keep_names,
+            start_at,
+            stop_at,
+            duration,
+            max_segments,
+            max_size,
+            rolling_window,
+            poll_interval,
+            concurrency,
+            max_connections,
+            max_connections_per_host,
+            proxy,
+            ca_certs,
+            client_cert,
+            client_key,
+            insecure,
+            connect_timeout,
+            read_timeout,
+            user_agent,
+            auth_command,
+            auth_url,
+            auth_refresh,
+            auth_timeout,
+            cookies,
+            save_cookies,
+            webhooks,
+            on_event,
+            progress,
         } => {
             let variant_select = if let Some(bandwidth) = bandwidth {
                 VariantSelectOptions::Bandwidth(bandwidth)
             } else {
                 VariantSelectOptions::Named(variant)
             };
+            let start_at = start_at.map(|start_at| start_at.resolve_after(Utc::now()));
+            let stop_at =
+                stop_at.map(|stop_at| stop_at.resolve_after(start_at.unwrap_or_else(Utc::now)));
+            let auth: Option<Arc<dyn AuthSource>> = match (auth_command, auth_url) {
+                (Some(command), _) => Some(Arc::new(CommandAuthSource { command })),
+                (None, Some(url)) => Some(Arc::new(EndpointAuthSource { url })),
+                (None, None) => None,
+            };
+            let mut event_hooks = webhooks
+                .into_iter()
+                .map(EventHook::Webhook)
+                .collect::<Vec<_>>();
+            event_hooks.extend(on_event.map(EventHook::Command));
             let options = RecordOptions {
                 start,
                 end,
@@ -144,40 +422,135 @@ async fn main() {
                 subtitle,
                 headers: headers.into_iter().collect::<HeaderMap>(),
                 keep_names,
+                start_at,
+                stop_at,
+                duration,
+                max_segments,
+                max_size,
+                rolling_window,
+                poll_interval,
+                concurrent_downloads: Some(concurrency),
+                max_connections,
+                max_connections_per_host,
+                proxy,
+                ca_certs,
+                client_cert,
+                client_key,
+                insecure,
+                connect_timeout,
+                read_timeout,
+                user_agent,
+                auth,
+                auth_refresh_interval: auth_refresh,
+                auth_timeout: Some(auth_timeout),
+                cookies,
+                save_cookies,
+                event_hooks,
             };
+            let record_progress = RecordProgress::new();
+            let progress_task = progress.then(|| {
+                spawn(show_progress(
+                    record_progress.clone(),
+                    multi_progress.clone(),
+                ))
+            });
             let token = CancellationToken::new();
             let record_task = {
                 let token = token.clone();
                 spawn(async move {
-                    streamrr::record::record(&manifest_url, &recording_path, options, token).await
+                    streamrr::record::record(
+                        &manifest_url,
+                        &recording_path,
+                        options,
+                        record_progress,
+                        token,
+                    )
+                    .await
                 })
             };
-            match abort_on_ctrlc(record_task, token, RecordError::Cancelled).await {
+            let result = abort_on_ctrlc(record_task, token, RecordError::Cancelled).await;
+            if let Some(progress_task) = progress_task {
+                progress_task.abort();
+            }
+            match result {
                 Ok(()) => {}
-                Err(RecordError::Cancelled) => println!("Stopped recording."),
+                Err(RecordError::Cancelled) => info!("Stopped recording."),
                 Err(e) => {
-                    eprintln!("{e}");
+                    error!("{e}");
                     std::process::exit(1);
                 }
             };
         }
+        CliCommand::Recover { recording_path } => {
+            if let Err(e) = streamrr::record::recover(&recording_path).await {
+                error!("{e}");
+                std::process::exit(1);
+            }
+        }
         CliCommand::Replay {
-            recording_path,
+            recording_paths,
             address,
             port,
+            headers,
+            drop_headers,
+            tls_cert,
+            tls_key,
+            tls_self_signed,
+            session,
+            clock_start,
+            rewrite_pdt,
+            simulate_live,
+            live_window,
+            live_event,
+            live_endlist,
+            dvr_window,
+            dvr_event,
         } => {
+            let tls = match (tls_cert, tls_key) {
+                (Some(cert), Some(key)) => Some(TlsOptions::Files { cert, key }),
+                _ if tls_self_signed => Some(TlsOptions::SelfSigned),
+                _ => None,
+            };
+            let options = ReplayOptions {
+                headers: headers.into_iter().collect::<HeaderMap>(),
+                drop_headers,
+                tls,
+                session,
+                clock_start,
+                rewrite_pdt,
+                simulate_live: simulate_live.then_some(SimulateLiveOptions {
+                    window: live_window,
+                    event: live_event,
+                    end_list: live_endlist,
+                }),
+                dvr: (dvr_window.is_some() || dvr_event)
+                    .then_some(DvrOptions { window: dvr_window }),
+            };
             let token = CancellationToken::new();
             let replay_task = {
                 let token = token.clone();
                 spawn(async move {
-                    streamrr::replay::replay(&recording_path, address, port, token).await
+                    let server = ReplayServer::new(recording_paths)
+                        .address(address)
+                        .port(port)
+                        .options(options)
+                        .start()
+                        .await?;
+                    // Let other programs know where to find the server
+                    let ready = json!({
+                        "type": "ready",
+                        "address": server.local_addr(),
+                        "url": server.url(),
+                    });
+                    println!("{ready}");
+                    server.run_until_cancelled(token).await
                 })
             };
             match abort_on_ctrlc(replay_task, token, ReplayError::Cancelled).await {
                 Ok(()) => {}
-                Err(ReplayError::Cancelled) => println!("Stopped replaying."),
+                Err(ReplayError::Cancelled) => info!("Stopped replaying."),
                 Err(e) => {
-                    eprintln!("{e}");
+                    error!("{e}");
                     std::process::exit(1);
                 }
             };
@@ -195,3 +568,130 @@ fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
         HeaderValue::from_str(value.trim()).map_err(|e| e.to_string())?,
     ))
 }
+
+/// A point in time, given either as a full date and time or as a time of day.
+#[derive(Debug, Copy, Clone)]
+enum WallClockTime {
+    DateTime(DateTime<Utc>),
+    TimeOfDay(NaiveTime),
+}
+
+impl WallClockTime {
+    /// Resolve to a date and time, using the first occurrence of a time of day after `after`.
+    fn resolve_after(self, after: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            WallClockTime::DateTime(date_time) => date_time,
+            WallClockTime::TimeOfDay(time) => {
+                let date_time = after.date_naive().and_time(time).and_utc();
+                if date_time > after {
+                    date_time
+                } else {
+                    date_time + Days::new(1)
+                }
+            }
+        }
+    }
+}
+
+fn parse_wall_clock_time(s: &str) -> Result<WallClockTime, String> {
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(WallClockTime::DateTime(date_time.to_utc()));
+    }
+    NaiveTime::parse_from_str(s, "%H:%M:%S")
+        .or_else(|_| NaiveTime::parse_from_str(s, "%H:%M"))
+        .map(WallClockTime::TimeOfDay)
+        .map_err(|_| {
+            "invalid time (expected \"YYYY-MM-DDTHH:MM:SSZ\" or \"HH:MM[:SS]\")".to_string()
+        })
+}
+
+fn parse_size(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let unit_start = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(unit_start);
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| "invalid size (expected e.g. \"500MB\" or \"2GB\")".to_string())?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" => 1_000,
+        "M" | "MB" => 1_000_000,
+        "G" | "GB" => 1_000_000_000,
+        "T" | "TB" => 1_000_000_000_000,
+        "KIB" => 1 << 10,
+        "MIB" => 1 << 20,
+        "GIB" => 1 << 30,
+        "TIB" => 1 << 40,
+        _ => return Err(format!("invalid size unit: {unit}")),
+    };
+    Ok((value * multiplier as f64) as u64)
+}
+
+fn init_logging(log_level: &str, log_format: LogFormat, multi_progress: &MultiProgress) {
+    let filter = EnvFilter::try_new(log_level).unwrap_or_else(|e| {
+        eprintln!("Invalid log level: {e}");
+        std::process::exit(2);
+    });
+    let multi_progress = multi_progress.clone();
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(io::stderr().is_terminal())
+        .with_writer(move || LogWriter(multi_progress.clone()));
+    match log_format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Writes log messages to stderr, without garbling the progress bars.
+struct LogWriter(MultiProgress);
+
+impl Write for LogWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.suspend(|| io::stderr().write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
+
+async fn show_progress(progress: RecordProgress, multi_progress: MultiProgress) {
+    let style = ProgressStyle::with_template("{spinner} {prefix:.bold} {msg}").unwrap();
+    let mut bars = IndexMap::<String, ProgressBar>::new();
+    let mut interval = tokio::time::interval(Duration::from_millis(250));
+    loop {
+        interval.tick().await;
+        for (name, playlist) in progress.playlists() {
+            let bar = bars.entry(name).or_insert_with_key(|name| {
+                let bar = ProgressBar::new_spinner()
+                    .with_style(style.clone())
+                    .with_prefix(name.clone());
+                multi_progress.add(bar)
+            });
+            if bar.is_finished() {
+                continue;
+            }
+            bar.set_message(format_progress(&playlist));
+            if playlist.ended {
+                bar.finish();
+            } else {
+                bar.tick();
+            }
+        }
+    }
+}
+
+fn format_progress(playlist: &PlaylistProgress) -> String {
+    format!(
+        "sequence {} | {} segments | {} | {}/s | {:.1}s behind{}",
+        playlist.media_sequence,
+        playlist.segments,
+        HumanBytes(playlist.bytes),
+        HumanBytes(playlist.throughput as u64),
+        playlist.lag.as_secs_f32(),
+        if playlist.ended { " | ended" } else { "" }
+    )
+}
@@ -0,0 +1,435 @@
+use std::fmt;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use m3u8_rs::MediaPlaylist;
+use serde::Serialize;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+use crate::record::rewrite::stable_url;
+
+/// The name of the report with all anomalies, in the recording directory.
+pub(crate) const ANOMALIES_FILE_NAME: &str = "anomalies.jsonl";
+
+/// How long a live playlist may stay unchanged, relative to its target duration.
+const STALE_FACTOR: f64 = 1.5;
+
+/// An unexpected change between consecutive snapshots of a live media playlist,
+/// which usually points to a bug in the origin or packager.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum Anomaly {
+    /// The media sequence number is lower than in the previous playlist.
+    MediaSequenceBackwards { previous: u64, current: u64 },
+    /// A segment has a different URI or duration than in the previous playlist,
+    /// under the same media sequence number.
+    SegmentChanged {
+        media_sequence: u64,
+        previous_uri: String,
+        uri: String,
+        previous_duration: f32,
+        duration: f32,
+    },
+    /// The discontinuity sequence number doesn't match the discontinuities
+    /// removed from the start of the playlist.
+    DiscontinuitySequenceMismatch { expected: u64, current: u64 },
+    /// The target duration is different than in the previous playlist.
+    TargetDurationChanged { previous: u64, current: u64 },
+    /// A segment is longer than the target duration, after rounding.
+    TargetDurationExceeded {
+        media_sequence: u64,
+        duration: f32,
+        target_duration: u64,
+    },
+    /// The playlist hasn't changed for longer than 1.5 times the target duration.
+    StalePlaylist {
+        /// The time since the playlist last changed, in seconds.
+        unchanged_for: f64,
+        target_duration: u64,
+    },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::MediaSequenceBackwards { previous, current } => {
+                write!(
+                    f,
+                    "media sequence went backwards from {previous} to {current}"
+                )
+            }
+            Anomaly::SegmentChanged {
+                media_sequence,
+                previous_uri,
+                uri,
+                previous_duration,
+                duration,
+            } => write!(
+                f,
+                "segment {media_sequence} changed from {previous_uri} ({previous_duration}s) to {uri} ({duration}s)"
+            ),
+            Anomaly::DiscontinuitySequenceMismatch { expected, current } => write!(
+                f,
+                "discontinuity sequence is {current}, but expected {expected}"
+            ),
+            Anomaly::TargetDurationChanged { previous, current } => {
+                write!(f, "target duration changed from {previous}s to {current}s")
+            }
+            Anomaly::TargetDurationExceeded {
+                media_sequence,
+                duration,
+                target_duration,
+            } => write!(
+                f,
+                "segment {media_sequence} is {duration}s long, exceeding the target duration of {target_duration}s"
+            ),
+            Anomaly::StalePlaylist {
+                unchanged_for,
+                target_duration,
+            } => write!(
+                f,
+                "playlist has not changed for {unchanged_for:.1}s, with a target duration of {target_duration}s"
+            ),
+        }
+    }
+}
+
+/// Compares every downloaded snapshot of a live media playlist with the previous one.
+#[derive(Debug)]
+pub(crate) struct AnomalyDetector {
+    /// The URL of the media playlist, to resolve segment URIs.
+    url: Url,
+    /// The query parameters that may change between snapshots, such as authentication tokens.
+    volatile_query_params: Vec<String>,
+    previous: Option<MediaPlaylist>,
+    /// When the playlist last changed.
+    last_changed: Option<DateTime<Utc>>,
+    /// Whether the current stale period was already reported.
+    stale_reported: bool,
+}
+
+impl AnomalyDetector {
+    pub(crate) fn new(url: &Url, volatile_query_params: Vec<String>) -> Self {
+        Self {
+            url: url.clone(),
+            volatile_query_params,
+            previous: None,
+            last_changed: None,
+            stale_reported: false,
+        }
+    }
+
+    /// Find the anomalies in `playlist`, downloaded at `time`,
+    /// compared to the previously checked playlist.
+    pub(crate) fn check(&mut self, playlist: &MediaPlaylist, time: DateTime<Utc>) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+        let next_media_sequence = playlist.media_sequence + playlist.segments.len() as u64;
+        let mut new_media_sequence = playlist.media_sequence;
+        if let Some(previous) = &self.previous {
+            let previous_next_media_sequence =
+                previous.media_sequence + previous.segments.len() as u64;
+            new_media_sequence = previous_next_media_sequence;
+            if playlist.media_sequence < previous.media_sequence {
+                anomalies.push(Anomaly::MediaSequenceBackwards {
+                    previous: previous.media_sequence,
+                    current: playlist.media_sequence,
+                });
+            }
+            // Compare the segments that are in both playlists
+            for (media_sequence, segment) in (playlist.media_sequence..).zip(&playlist.segments) {
+                let Some(previous_segment) = media_sequence
+                    .checked_sub(previous.media_sequence)
+                    .and_then(|index| previous.segments.get(index as usize))
+                else {
+                    continue;
+                };
+                if !self.same_uri(&segment.uri, &previous_segment.uri)
+                    || (segment.duration - previous_segment.duration).abs() > 0.001
+                {
+                    anomalies.push(Anomaly::SegmentChanged {
+                        media_sequence,
+                        previous_uri: previous_segment.uri.clone(),
+                        uri: segment.uri.clone(),
+                        previous_duration: previous_segment.duration,
+                        duration: segment.duration,
+                    });
+                }
+            }
+            // Every discontinuity removed from the start increments the discontinuity sequence
+            if playlist.media_sequence >= previous.media_sequence
+                && playlist.media_sequence <= previous_next_media_sequence
+            {
+                let removed_count = (playlist.media_sequence - previous.media_sequence) as usize;
+                let removed_discontinuities = previous.segments[..removed_count]
+                    .iter()
+                    .filter(|segment| segment.discontinuity)
+                    .count() as u64;
+                let expected = previous.discontinuity_sequence + removed_discontinuities;
+                if playlist.discontinuity_sequence != expected {
+                    anomalies.push(Anomaly::DiscontinuitySequenceMismatch {
+                        expected,
+                        current: playlist.discontinuity_sequence,
+                    });
+                }
+            }
+            if playlist.target_duration != previous.target_duration {
+                anomalies.push(Anomaly::TargetDurationChanged {
+                    previous: previous.target_duration,
+                    current: playlist.target_duration,
+                });
+            }
+            // Check whether the playlist is still updated
+            if next_media_sequence != previous_next_media_sequence
+                || playlist.end_list != previous.end_list
+            {
+                self.last_changed = Some(time);
+                self.stale_reported = false;
+            } else if let Some(last_changed) = self.last_changed {
+                let unchanged_for = (time - last_changed).as_seconds_f64();
+                if !self.stale_reported
+                    && !playlist.end_list
+                    && unchanged_for > STALE_FACTOR * playlist.target_duration as f64
+                {
+                    anomalies.push(Anomaly::StalePlaylist {
+                        unchanged_for,
+                        target_duration: playlist.target_duration,
+                    });
+                    self.stale_reported = true;
+                }
+            }
+        } else {
+            self.last_changed = Some(time);
+        }
+        // Only check new segments, so every segment is reported once
+        let new_segments = (playlist.media_sequence..)
+            .zip(&playlist.segments)
+            .filter(|(media_sequence, _)| *media_sequence >= new_media_sequence);
+        for (media_sequence, segment) in new_segments {
+            if segment.duration.round() as u64 > playlist.target_duration {
+                anomalies.push(Anomaly::TargetDurationExceeded {
+                    media_sequence,
+                    duration: segment.duration,
+                    target_duration: playlist.target_duration,
+                });
+            }
+        }
+        self.previous = Some(playlist.clone());
+        anomalies
+    }
+
+    /// Whether two segment URIs are the same, ignoring the volatile query parameters.
+    fn same_uri(&self, uri: &str, other: &str) -> bool {
+        if uri == other {
+            return true;
+        }
+        match (self.url.join(uri), self.url.join(other)) {
+            (Ok(url), Ok(other_url)) => {
+                stable_url(&url, &self.volatile_query_params)
+                    == stable_url(&other_url, &self.volatile_query_params)
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct AnomalyReport<'a> {
+    time: DateTime<Utc>,
+    playlist: &'a str,
+    #[serde(flatten)]
+    anomaly: &'a Anomaly,
+}
+
+/// Append anomalies to the report in the recording directory, as JSON lines.
+pub(crate) async fn report_anomalies(
+    dest: &Path,
+    playlist_name: &str,
+    time: DateTime<Utc>,
+    anomalies: &[Anomaly],
+) -> std::io::Result<()> {
+    let mut report = String::new();
+    for anomaly in anomalies {
+        let line = AnomalyReport {
+            time,
+            playlist: playlist_name,
+            anomaly,
+        };
+        report.push_str(&serde_json::to_string(&line)?);
+        report.push('\n');
+    }
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dest.join(ANOMALIES_FILE_NAME))
+        .await?;
+    file.write_all(report.as_bytes()).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeDelta;
+    use m3u8_rs::MediaSegment;
+
+    fn live_playlist(media_sequence: u64, count: u64) -> MediaPlaylist {
+        MediaPlaylist {
+            target_duration: 4,
+            media_sequence,
+            segments: (media_sequence..media_sequence + count)
+                .map(|index| MediaSegment {
+                    uri: format!("segment-{index}.ts"),
+                    duration: 4.0,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn detector() -> AnomalyDetector {
+        let url = Url::parse("https://example.com/live/media.m3u8").unwrap();
+        AnomalyDetector::new(&url, vec!["token".to_string()])
+    }
+
+    fn at(seconds: i64) -> DateTime<Utc> {
+        DateTime::UNIX_EPOCH + TimeDelta::seconds(seconds)
+    }
+
+    #[test]
+    fn no_anomalies() {
+        let mut detector = detector();
+        assert_eq!(detector.check(&live_playlist(10, 3), at(0)), []);
+        assert_eq!(detector.check(&live_playlist(10, 3), at(2)), []);
+        assert_eq!(detector.check(&live_playlist(11, 3), at(4)), []);
+        assert_eq!(detector.check(&live_playlist(13, 3), at(12)), []);
+    }
+
+    #[test]
+    fn media_sequence_and_segment_changes() {
+        let mut detector = detector();
+        detector.check(&live_playlist(10, 3), at(0));
+        let mut playlist = live_playlist(9, 5);
+        playlist.segments[2].uri = "other.ts".to_string();
+        playlist.segments[3].duration = 3.0;
+        assert_eq!(
+            detector.check(&playlist, at(4)),
+            [
+                Anomaly::MediaSequenceBackwards {
+                    previous: 10,
+                    current: 9
+                },
+                Anomaly::SegmentChanged {
+                    media_sequence: 11,
+                    previous_uri: "segment-11.ts".to_string(),
+                    uri: "other.ts".to_string(),
+                    previous_duration: 4.0,
+                    duration: 4.0,
+                },
+                Anomaly::SegmentChanged {
+                    media_sequence: 12,
+                    previous_uri: "segment-12.ts".to_string(),
+                    uri: "segment-12.ts".to_string(),
+                    previous_duration: 4.0,
+                    duration: 3.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn discontinuity_sequence_mismatch() {
+        let mut detector = detector();
+        let mut playlist = live_playlist(10, 3);
+        playlist.segments[0].discontinuity = true;
+        playlist.segments[1].discontinuity = true;
+        detector.check(&playlist, at(0));
+        let mut playlist = live_playlist(12, 3);
+        playlist.discontinuity_sequence = 1;
+        assert_eq!(
+            detector.check(&playlist, at(8)),
+            [Anomaly::DiscontinuitySequenceMismatch {
+                expected: 2,
+                current: 1
+            }]
+        );
+    }
+
+    #[test]
+    fn target_duration_violations() {
+        let mut detector = detector();
+        let mut playlist = live_playlist(10, 3);
+        playlist.segments[2].duration = 4.4;
+        assert_eq!(detector.check(&playlist, at(0)), []);
+        let mut playlist = live_playlist(10, 4);
+        playlist.segments[2].duration = 4.4;
+        playlist.segments[3].duration = 4.5;
+        assert_eq!(
+            detector.check(&playlist, at(4)),
+            [Anomaly::TargetDurationExceeded {
+                media_sequence: 13,
+                duration: 4.5,
+                target_duration: 4,
+            }]
+        );
+        let mut playlist = live_playlist(11, 3);
+        playlist.target_duration = 6;
+        playlist.segments[1].duration = 4.4;
+        playlist.segments[2].duration = 4.5;
+        assert_eq!(
+            detector.check(&playlist, at(8)),
+            [Anomaly::TargetDurationChanged {
+                previous: 4,
+                current: 6
+            }]
+        );
+    }
+
+    #[test]
+    fn stale_playlist() {
+        let mut detector = detector();
+        detector.check(&live_playlist(10, 3), at(0));
+        assert_eq!(detector.check(&live_playlist(10, 3), at(6)), []);
+        assert_eq!(
+            detector.check(&live_playlist(10, 3), at(7)),
+            [Anomaly::StalePlaylist {
+                unchanged_for: 7.0,
+                target_duration: 4
+            }]
+        );
+        // Only reported once, until the playlist changes again
+        assert_eq!(detector.check(&live_playlist(10, 3), at(9)), []);
+        assert_eq!(detector.check(&live_playlist(11, 3), at(10)), []);
+        assert_eq!(detector.check(&live_playlist(11, 3), at(16)), []);
+        assert_eq!(detector.check(&live_playlist(11, 3), at(17)).len(), 1);
+    }
+
+    #[test]
+    fn volatile_query_params_ignored() {
+        let mut detector = detector();
+        let mut playlist = live_playlist(10, 3);
+        for segment in &mut playlist.segments {
+            segment.uri.push_str("?token=abc&variant=1");
+        }
+        detector.check(&playlist, at(0));
+        let mut playlist = live_playlist(10, 3);
+        for segment in &mut playlist.segments {
+            segment.uri.push_str("?token=def&variant=1");
+        }
+        assert_eq!(detector.check(&playlist, at(2)), []);
+        // Other query parameters are still compared
+        playlist.segments[1].uri = "segment-11.ts?token=ghi&variant=2".to_string();
+        assert_eq!(
+            detector.check(&playlist, at(4)),
+            [Anomaly::SegmentChanged {
+                media_sequence: 11,
+                previous_uri: "segment-11.ts?token=def&variant=1".to_string(),
+                uri: "segment-11.ts?token=ghi&variant=2".to_string(),
+                previous_duration: 4.0,
+                duration: 4.0,
+            }]
+        );
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::BTreeSet;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, anyhow, bail};
+use futures::future::{BoxFuture, FutureExt};
+use reqwest::Client;
+use tokio::process::Command;
+use tokio::sync::Mutex;
+use tracing::info;
+use url::Url;
+
+use crate::record::RecordError;
+
+/// The query parameters to add to every request URL, e.g. `[("token", "abc")]`.
+pub type AuthParams = Vec<(String, String)>;
+
+/// A source of authentication parameters for a recording,
+/// such as a token for signed URLs.
+///
+/// The parameters are added to the query of every playlist and segment URL,
+/// replacing any existing parameters with the same name.
+pub trait AuthSource: Debug + Send + Sync {
+    /// Fetch new authentication parameters.
+    fn fetch<'a>(&'a self, client: &'a Client) -> BoxFuture<'a, anyhow::Result<AuthParams>>;
+}
+
+/// Runs an external command, and reads the authentication parameters from its output.
+///
+/// The output must be either a query string (e.g. `token=abc&expires=123`)
+/// or a JSON object (e.g. `{"token": "abc", "expires": 123}`).
+#[derive(Debug, Clone)]
+pub struct CommandAuthSource {
+    /// The command to run, interpreted by the system shell.
+    pub command: String,
+}
+
+/// Fetches the authentication parameters from a token endpoint.
+///
+/// The response body must be either a query string or a JSON object,
+/// as with [`CommandAuthSource`].
+#[derive(Debug, Clone)]
+pub struct EndpointAuthSource {
+    pub url: Url,
+}
+
+impl AuthSource for CommandAuthSource {
+    fn fetch<'a>(&'a self, _client: &'a Client) -> BoxFuture<'a, anyhow::Result<AuthParams>> {
+        async move {
+            let output = shell_command(&self.command)
+                .kill_on_drop(true)
+                .output()
+                .await
+                .with_context(|| format!("Error while running {}", self.command))?;
+            if !output.status.success() {
+                bail!(
+                    "{} failed with {}: {}",
+                    self.command,
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            parse_auth_params(&String::from_utf8_lossy(&output.stdout))
+        }
+        .boxed()
+    }
+}
+
+/// Build a command that runs `command` with the system shell.
+pub(crate) fn shell_command(command: &str) -> Command {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    shell.arg(command);
+    shell
+}
+
+impl AuthSource for EndpointAuthSource {
+    fn fetch<'a>(&'a self, client: &'a Client) -> BoxFuture<'a, anyhow::Result<AuthParams>> {
+        async move {
+            let body = client
+                .get(self.url.clone())
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+                .with_context(|| format!("Error while requesting {}", self.url))?
+                .text()
+                .await?;
+            parse_auth_params(&body)
+        }
+        .boxed()
+    }
+}
+
+/// Parse authentication parameters from a query string or a JSON object.
+fn parse_auth_params(input: &str) -> anyhow::Result<AuthParams> {
+    let input = input.trim();
+    if input.starts_with('{') {
+        let object: serde_json::Map<String, serde_json::Value> = serde_json::from_str(input)?;
+        object
+            .into_iter()
+            .map(|(name, value)| match value {
+                serde_json::Value::String(value) => Ok((name, value)),
+                serde_json::Value::Number(value) => Ok((name, value.to_string())),
+                serde_json::Value::Bool(value) => Ok((name, value.to_string())),
+                _ => Err(anyhow!(
+                    "Unsupported value for authentication parameter {name}"
+                )),
+            })
+            .collect()
+    } else {
+        let query = input.strip_prefix('?').unwrap_or(input);
+        Ok(url::form_urlencoded::parse(query.as_bytes())
+            .into_owned()
+            .collect())
+    }
+}
+
+/// Keeps the authentication parameters of a recording up-to-date.
+#[derive(Debug)]
+pub(crate) struct Authenticator {
+    source: Arc<dyn AuthSource>,
+    refresh_interval: Option<Duration>,
+    timeout: Duration,
+    state: Mutex<AuthState>,
+}
+
+#[derive(Debug, Default)]
+struct AuthState {
+    params: AuthParams,
+    fetched_at: Option<Instant>,
+    /// The names of all parameters fetched so far
+    names: BTreeSet<String>,
+}
+
+impl Authenticator {
+    pub(crate) fn new(
+        source: Arc<dyn AuthSource>,
+        refresh_interval: Option<Duration>,
+        timeout: Duration,
+    ) -> Self {
+        Self {
+            source,
+            refresh_interval,
+            timeout,
+            state: Mutex::new(AuthState::default()),
+        }
+    }
+
+    /// Get the current authentication parameters, fetching new ones if they have expired.
+    pub(crate) async fn params(&self, client: &Client) -> Result<AuthParams, RecordError> {
+        let mut state = self.state.lock().await;
+        let expired = match state.fetched_at {
+            None => true,
+            Some(fetched_at) => self
+                .refresh_interval
+                .is_some_and(|interval| fetched_at.elapsed() >= interval),
+        };
+        if expired {
+            self.fetch(client, &mut state).await?;
+        }
+        Ok(state.params.clone())
+    }
+
+    /// Fetch new authentication parameters, because the given parameters were rejected.
+    ///
+    /// If the parameters were already refreshed in the meantime (e.g. by another request),
+    /// the new parameters are returned without fetching again.
+    pub(crate) async fn refresh(
+        &self,
+        client: &Client,
+        rejected: &AuthParams,
+    ) -> Result<AuthParams, RecordError> {
+        let mut state = self.state.lock().await;
+        if state.params == *rejected {
+            self.fetch(client, &mut state).await?;
+        }
+        Ok(state.params.clone())
+    }
+
+    /// The names of all authentication parameters fetched so far.
+    pub(crate) async fn param_names(&self) -> Vec<String> {
+        self.state.lock().await.names.iter().cloned().collect()
+    }
+
+    async fn fetch(&self, client: &Client, state: &mut AuthState) -> Result<(), RecordError> {
+        info!("Refreshing authentication");
+        // Other requests wait for the lock on the state, so never let a stuck source block them
+        let params = tokio::time::timeout(self.timeout, self.source.fetch(client))
+            .await
+            .map_err(|_| {
+                RecordError::Auth(anyhow!(
+                    "Timed out after {} while fetching authentication parameters",
+                    humantime::format_duration(self.timeout)
+                ))
+            })?
+            .map_err(RecordError::Auth)?;
+        state
+            .names
+            .extend(params.iter().map(|(name, _)| name.clone()));
+        state.params = params;
+        state.fetched_at = Some(Instant::now());
+        Ok(())
+    }
+}
+
+/// Set the given query parameters on a URL, replacing any existing parameters with the same name.
+pub(crate) fn apply_auth_params(url: &Url, params: &AuthParams) -> Url {
+    let mut url = url.clone();
+    if params.is_empty() {
+        return url;
+    }
+    let query: AuthParams = url
+        .query_pairs()
+        .filter(|(name, _)| params.iter().all(|(param, _)| param != name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .chain(params.iter().cloned())
+        .collect();
+    url.query_pairs_mut().clear().extend_pairs(query);
+    url
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_and_apply_params() {
+        let params = |pairs: &[(&str, &str)]| -> AuthParams {
+            pairs
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect()
+        };
+        let expected = params(&[("expires", "123"), ("token", "a b")]);
+        assert_eq!(
+            parse_auth_params("?expires=123&token=a+b\n").unwrap(),
+            expected
+        );
+        assert_eq!(
+            parse_auth_params(r#"{"token": "a b", "expires": 123}"#).unwrap(),
+            expected
+        );
+        assert!(parse_auth_params(r#"{"token": null}"#).is_err());
+
+        let url = Url::parse("https://a.com/video.m3u8?token=old&id=1").unwrap();
+        assert_eq!(
+            apply_auth_params(&url, &expected).as_str(),
+            "https://a.com/video.m3u8?id=1&expires=123&token=a+b"
+        );
+        let url = Url::parse("https://a.com/video.m3u8").unwrap();
+        assert_eq!(apply_auth_params(&url, &Vec::new()), url);
+        let url = Url::parse("https://a.com/video.m3u8?token=old&id=1").unwrap();
+        assert_eq!(apply_auth_params(&url, &Vec::new()), url);
+    }
+
+    #[derive(Debug)]
+    struct StuckAuthSource;
+
+    impl AuthSource for StuckAuthSource {
+        fn fetch<'a>(&'a self, _client: &'a Client) -> BoxFuture<'a, anyhow::Result<AuthParams>> {
+            futures::future::pending().boxed()
+        }
+    }
+
+    #[tokio::test]
+    async fn fetch_times_out() {
+        let authenticator =
+            Authenticator::new(Arc::new(StuckAuthSource), None, Duration::from_millis(10));
+        let result = authenticator.params(&Client::new()).await;
+        assert!(matches!(result, Err(RecordError::Auth(_))), "{result:?}");
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
+use std::sync::{Arc, Mutex};
+
+use anyhow::anyhow;
+use reqwest::header::HeaderMap;
+use reqwest::{Certificate, Client, Identity, Proxy, Response, StatusCode};
+use tokio::fs;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tracing::warn;
+use url::Url;
+
+use crate::record::auth::{Authenticator, apply_auth_params};
+use crate::record::cookies::CookieJar;
+use crate::record::{RecordError, RecordOptions};
+
+/// Build the HTTP client for a recording.
+pub(crate) async fn build_client(
+    options: &RecordOptions,
+    cookie_jar: Arc<CookieJar>,
+) -> Result<Client, RecordError> {
+    let mut builder = Client::builder()
+        .cookie_provider(cookie_jar)
+        .default_headers(options.headers.clone());
+    if let Some(user_agent) = &options.user_agent {
+        builder = builder.user_agent(user_agent);
+    }
+    if let Some(proxy) = &options.proxy {
+        let proxy = Proxy::all(proxy.clone())
+            .map_err(|e| RecordError::Parse(anyhow!("Invalid proxy URL {proxy}: {e}")))?;
+        builder = builder.proxy(proxy);
+    }
+    for path in &options.ca_certs {
+        let certs = Certificate::from_pem_bundle(&fs::read(path).await?).map_err(|e| {
+            RecordError::Parse(anyhow!(
+                "Error while parsing CA certificate {}: {e}",
+                path.display()
+            ))
+        })?;
+        builder = builder.tls_certs_merge(certs);
+    }
+    if let Some(cert_path) = &options.client_cert {
+        let mut pem = fs::read(cert_path).await?;
+        if let Some(key_path) = &options.client_key {
+            pem.push(b'\n');
+            pem.extend(fs::read(key_path).await?);
+        }
+        let identity = Identity::from_pem(&pem).map_err(|e| {
+            RecordError::Parse(anyhow!(
+                "Error while parsing client certificate {}: {e}",
+                cert_path.display()
+            ))
+        })?;
+        builder = builder.identity(identity);
+    } else if options.client_key.is_some() {
+        return Err(RecordError::Config(
+            "Client key given without client certificate",
+        ));
+    }
+    if options.insecure {
+        builder = builder
+            .tls_danger_accept_invalid_certs(true)
+            .tls_danger_accept_invalid_hostnames(true);
+    }
+    if let Some(timeout) = options.connect_timeout {
+        builder = builder.connect_timeout(timeout);
+    }
+    if let Some(timeout) = options.read_timeout {
+        builder = builder.read_timeout(timeout);
+    }
+    builder.build().map_err(|e| {
+        // Include the underlying cause, since the error itself only says "builder error"
+        RecordError::Parse(anyhow!(
+            "Error while building HTTP client: {:#}",
+            anyhow::Error::new(e)
+        ))
+    })
+}
+
+/// An HTTP client that limits the number of concurrent connections,
+/// both in total and for each host, and authenticates every request.
+///
+/// This is cheap to clone, all clones share the same connection limits
+/// and authentication.
+#[derive(Debug, Clone)]
+pub(crate) struct HttpClient {
+    client: Client,
+    limits: Arc<ConnectionLimits>,
+    auth: Option<Arc<Authenticator>>,
+}
+
+#[derive(Debug)]
+struct ConnectionLimits {
+    total: Option<Arc<Semaphore>>,
+    per_host: Option<NonZeroUsize>,
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+}
+
+/// Permission to make a request, released when dropped.
+#[must_use]
+pub(crate) struct ConnectionPermit {
+    _host: Option<OwnedSemaphorePermit>,
+    _total: Option<OwnedSemaphorePermit>,
+}
+
+impl HttpClient {
+    pub(crate) fn new(
+        client: Client,
+        max_connections: Option<NonZeroUsize>,
+        max_connections_per_host: Option<NonZeroUsize>,
+        auth: Option<Authenticator>,
+    ) -> Self {
+        Self {
+            client,
+            limits: Arc::new(ConnectionLimits {
+                total: max_connections.map(|max| Arc::new(Semaphore::new(max.get()))),
+                per_host: max_connections_per_host,
+                hosts: Mutex::new(HashMap::new()),
+            }),
+            auth: auth.map(Arc::new),
+        }
+    }
+
+    /// Send a GET request with the current authentication parameters (if any).
+    ///
+    /// If the server rejects the request with 401 Unauthorized or 403 Forbidden,
+    /// the authentication parameters are refreshed and the request is retried once.
+    pub(crate) async fn get(&self, url: &Url, headers: HeaderMap) -> Result<Response, RecordError> {
+        let Some(auth) = &self.auth else {
+            return self.send(url.clone(), headers).await;
+        };
+        let params = auth.params(&self.client).await?;
+        let response = self
+            .send(apply_auth_params(url, &params), headers.clone())
+            .await?;
+        if !matches!(
+            response.status(),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN
+        ) {
+            return Ok(response);
+        }
+        warn!(%url, status = %response.status(), "Request rejected, refreshing authentication");
+        let params = auth.refresh(&self.client, &params).await?;
+        self.send(apply_auth_params(url, &params), headers).await
+    }
+
+    async fn send(&self, url: Url, headers: HeaderMap) -> Result<Response, RecordError> {
+        self.client
+            .get(url)
+            .headers(headers)
+            .send()
+            .await
+            .map_err(|e| RecordError::Io(std::io::Error::other(e)))
+    }
+
+    /// The names of the query parameters that are set by the authentication source.
+    ///
+    /// These change whenever the authentication is refreshed,
+    /// so they should not be used to build stable file names.
+    pub(crate) async fn volatile_query_params(&self) -> Vec<String> {
+        match &self.auth {
+            Some(auth) => auth.param_names().await,
+            None => vec![],
+        }
+    }
+
+    /// Wait until a new connection to the given URL is allowed.
+    ///
+    /// The connection should be kept open until the returned permit is dropped.
+    pub(crate) async fn acquire(&self, url: &Url) -> ConnectionPermit {
+        // Always acquire the host permit first, to avoid deadlocks.
+        let host = match self.host_semaphore(url) {
+            Some(semaphore) => Some(semaphore.acquire_owned().await.unwrap()),
+            None => None,
+        };
+        let total = match &self.limits.total {
+            Some(semaphore) => Some(semaphore.clone().acquire_owned().await.unwrap()),
+            None => None,
+        };
+        ConnectionPermit {
+            _host: host,
+            _total: total,
+        }
+    }
+
+    fn host_semaphore(&self, url: &Url) -> Option<Arc<Semaphore>> {
+        let max_per_host = self.limits.per_host?;
+        let host = format!(
+            "{}:{}",
+            url.host_str().unwrap_or_default(),
+            url.port_or_known_default().unwrap_or_default()
+        );
+        let mut hosts = self.limits.hosts.lock().unwrap();
+        let semaphore = hosts
+            .entry(host)
+            .or_insert_with(|| Arc::new(Semaphore::new(max_per_host.get())));
+        Some(semaphore.clone())
+    }
+}
@@ -0,0 +1,383 @@
+use std::str::FromStr;
+
+use anyhow::anyhow;
+use chrono::{DateTime, TimeDelta, Utc};
+use m3u8_rs::{MediaPlaylist, MediaSegment};
+use serde::Serialize;
+
+use super::RecordError;
+
+/// A position in a media playlist, used to select the first or last segment to record.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum PlaylistPosition {
+    /// An offset in seconds from the start (if positive) or from the end (if negative)
+    /// of the first media playlist.
+    Offset(f32),
+    /// An absolute time, matched against the `#EXT-X-PROGRAM-DATE-TIME` of the segments.
+    ProgramDateTime(DateTime<Utc>),
+}
+
+/// The result of looking up a [`PlaylistPosition`] in a media playlist.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum SegmentLookup {
+    /// The position is at the segment with this index.
+    Found(usize),
+    /// The position lies before the first segment.
+    Earlier,
+    /// The position lies after the last segment, and may appear in a future playlist.
+    Later,
+    /// The position cannot be found in this playlist.
+    NotFound,
+}
+
+impl PlaylistPosition {
+    /// Find the first segment to record when starting at this position.
+    pub(crate) fn find_start_index(&self, segments: &[MediaSegment]) -> SegmentLookup {
+        match *self {
+            PlaylistPosition::Offset(offset) => find_segment_index_by_offset(segments, offset)
+                .map_or(SegmentLookup::NotFound, SegmentLookup::Found),
+            PlaylistPosition::ProgramDateTime(time) => {
+                find_start_index_by_program_date_time(segments, time)
+            }
+        }
+    }
+
+    /// Find the last segment to record when ending at this position.
+    pub(crate) fn find_end_index(&self, segments: &[MediaSegment]) -> SegmentLookup {
+        match *self {
+            PlaylistPosition::Offset(offset) => find_segment_index_by_offset(segments, offset)
+                .map_or(SegmentLookup::NotFound, SegmentLookup::Found),
+            PlaylistPosition::ProgramDateTime(time) => {
+                find_end_index_by_program_date_time(segments, time)
+            }
+        }
+    }
+
+    fn program_date_time(&self) -> Option<DateTime<Utc>> {
+        match *self {
+            PlaylistPosition::Offset(_) => None,
+            PlaylistPosition::ProgramDateTime(time) => Some(time),
+        }
+    }
+}
+
+/// A start or end point of a recording, resolved against a reference media playlist
+/// so it can be applied to all media playlists of the recording.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum ClipPoint {
+    /// The segment with this media sequence number.
+    MediaSequence(u64),
+    /// The segment containing this program date time.
+    ProgramDateTime(DateTime<Utc>),
+}
+
+impl ClipPoint {
+    /// The clip point at the segment with the given index.
+    ///
+    /// Uses the program date time in the middle of the segment (if known),
+    /// so it still matches if the segments of other media playlists are slightly misaligned.
+    fn at_segment(playlist: &MediaPlaylist, index: usize) -> Self {
+        let segment = &playlist.segments[index];
+        match segment_program_date_times(&playlist.segments)[index] {
+            Some(start_time) => ClipPoint::ProgramDateTime(
+                start_time + TimeDelta::milliseconds((segment.duration * 500.0) as i64),
+            ),
+            None => ClipPoint::MediaSequence(playlist.media_sequence + (index as u64)),
+        }
+    }
+
+    /// Find the first segment to record when starting at this point.
+    pub(crate) fn find_start_index(&self, playlist: &MediaPlaylist) -> SegmentLookup {
+        match *self {
+            ClipPoint::MediaSequence(media_sequence) => {
+                find_index_by_media_sequence(playlist, media_sequence)
+            }
+            ClipPoint::ProgramDateTime(time) => {
+                find_start_index_by_program_date_time(&playlist.segments, time)
+            }
+        }
+    }
+
+    /// Find the last segment to record when ending at this point.
+    pub(crate) fn find_end_index(&self, playlist: &MediaPlaylist) -> SegmentLookup {
+        match *self {
+            ClipPoint::MediaSequence(media_sequence) => {
+                find_index_by_media_sequence(playlist, media_sequence)
+            }
+            ClipPoint::ProgramDateTime(time) => {
+                find_end_index_by_program_date_time(&playlist.segments, time)
+            }
+        }
+    }
+}
+
+pub(crate) const MISSING_START_PROGRAM_DATE_TIME: &str =
+    "Start time cannot be found, because the media playlist has no program date time.";
+pub(crate) const MISSING_END_PROGRAM_DATE_TIME: &str =
+    "End time cannot be found, because the media playlist has no program date time.";
+
+/// The start and end points of a recording.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub(crate) struct Clip {
+    pub start: Option<ClipPoint>,
+    pub end: Option<ClipPoint>,
+}
+
+impl Clip {
+    /// Resolve the start and end positions against a reference media playlist.
+    pub(crate) fn resolve(
+        start: Option<PlaylistPosition>,
+        end: Option<PlaylistPosition>,
+        playlist: &MediaPlaylist,
+    ) -> Result<Self, RecordError> {
+        let start = match start {
+            Some(start) => match start.find_start_index(&playlist.segments) {
+                SegmentLookup::Found(index) => Some(ClipPoint::at_segment(playlist, index)),
+                SegmentLookup::Later => start.program_date_time().map(ClipPoint::ProgramDateTime),
+                SegmentLookup::NotFound if start.program_date_time().is_some() => {
+                    return Err(RecordError::Config(MISSING_START_PROGRAM_DATE_TIME));
+                }
+                SegmentLookup::Earlier | SegmentLookup::NotFound => None,
+            },
+            None => None,
+        };
+        let end = match end {
+            Some(end) => match end.find_end_index(&playlist.segments) {
+                SegmentLookup::Found(index) => Some(ClipPoint::at_segment(playlist, index)),
+                SegmentLookup::Later => end.program_date_time().map(ClipPoint::ProgramDateTime),
+                SegmentLookup::Earlier => {
+                    return Err(RecordError::Config(
+                        "End time lies before the first segment of the media playlist.",
+                    ));
+                }
+                SegmentLookup::NotFound if end.program_date_time().is_some() => {
+                    return Err(RecordError::Config(MISSING_END_PROGRAM_DATE_TIME));
+                }
+                SegmentLookup::NotFound => None,
+            },
+            None => None,
+        };
+        Ok(Self { start, end })
+    }
+}
+
+impl FromStr for PlaylistPosition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(offset) = s.parse::<f32>() {
+            return Ok(PlaylistPosition::Offset(offset));
+        }
+        let time = DateTime::parse_from_rfc3339(s)
+            .map_err(|_| anyhow!("expected a number of seconds or an ISO 8601 date and time"))?;
+        Ok(PlaylistPosition::ProgramDateTime(time.to_utc()))
+    }
+}
+
+/// Compute the program date time of every segment.
+///
+/// Segments without an `#EXT-X-PROGRAM-DATE-TIME` tag get the end time of the previous segment,
+/// or `None` if no earlier segment has a program date time.
+fn segment_program_date_times(segments: &[MediaSegment]) -> Vec<Option<DateTime<Utc>>> {
+    let mut next_time = None;
+    segments
+        .iter()
+        .map(|segment| {
+            let time = segment
+                .program_date_time
+                .map(|time| time.to_utc())
+                .or(next_time);
+            next_time = time.map(|time| segment_end_time(time, segment));
+            time
+        })
+        .collect()
+}
+
+fn find_index_by_media_sequence(playlist: &MediaPlaylist, media_sequence: u64) -> SegmentLookup {
+    if media_sequence < playlist.media_sequence {
+        return SegmentLookup::Earlier;
+    }
+    let index = (media_sequence - playlist.media_sequence) as usize;
+    if index < playlist.segments.len() {
+        SegmentLookup::Found(index)
+    } else {
+        SegmentLookup::Later
+    }
+}
+
+fn find_start_index_by_program_date_time(
+    segments: &[MediaSegment],
+    time: DateTime<Utc>,
+) -> SegmentLookup {
+    let times = segment_program_date_times(segments);
+    if times.iter().all(Option::is_none) {
+        return SegmentLookup::NotFound;
+    }
+    // Start at the first segment that ends after the start time
+    times
+        .iter()
+        .zip(segments)
+        .position(|(start_time, segment)| {
+            start_time.is_some_and(|start_time| segment_end_time(start_time, segment) > time)
+        })
+        .map_or(SegmentLookup::Later, SegmentLookup::Found)
+}
+
+fn find_end_index_by_program_date_time(
+    segments: &[MediaSegment],
+    time: DateTime<Utc>,
+) -> SegmentLookup {
+    let times = segment_program_date_times(segments);
+    if times.iter().all(Option::is_none) {
+        return SegmentLookup::NotFound;
+    }
+    if let Some((Some(last_time), last_segment)) = times.last().zip(segments.last())
+        && segment_end_time(*last_time, last_segment) <= time
+    {
+        return SegmentLookup::Later;
+    }
+    // End at the last segment that starts at or before the end time
+    times
+        .iter()
+        .rposition(|start_time| start_time.is_some_and(|start_time| start_time <= time))
+        .map_or(SegmentLookup::Earlier, SegmentLookup::Found)
+}
+
+fn segment_end_time(start_time: DateTime<Utc>, segment: &MediaSegment) -> DateTime<Utc> {
+    start_time + TimeDelta::milliseconds((segment.duration * 1000.0) as i64)
+}
+
+fn find_segment_index_by_offset(segments: &[MediaSegment], offset: f32) -> Option<usize> {
+    #[inline]
+    fn find<'a>(
+        iter: impl Iterator<Item = (usize, &'a MediaSegment)>,
+        target_time: f32,
+    ) -> Option<usize> {
+        let mut start_time = 0.0;
+        for (index, segment) in iter {
+            let end_time = start_time + segment.duration;
+            if (start_time..end_time).contains(&target_time) {
+                return Some(index);
+            }
+            start_time = end_time;
+        }
+        None
+    }
+
+    if offset >= 0.0 {
+        find(segments.iter().enumerate(), offset)
+    } else {
+        find(segments.iter().enumerate().rev(), -offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segments_with_program_date_time(start: &str, durations: &[f32]) -> Vec<MediaSegment> {
+        let mut segments = durations
+            .iter()
+            .map(|&duration| MediaSegment {
+                duration,
+                ..MediaSegment::empty()
+            })
+            .collect::<Vec<_>>();
+        segments[0].program_date_time = Some(DateTime::parse_from_rfc3339(start).unwrap());
+        segments
+    }
+
+    fn at(time: &str) -> PlaylistPosition {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn parse_position() {
+        assert_eq!(
+            "-12.5".parse::<PlaylistPosition>().unwrap(),
+            PlaylistPosition::Offset(-12.5)
+        );
+        assert_eq!(
+            "2026-10-18T20:00:00+02:00"
+                .parse::<PlaylistPosition>()
+                .unwrap(),
+            PlaylistPosition::ProgramDateTime(
+                DateTime::parse_from_rfc3339("2026-10-18T18:00:00Z")
+                    .unwrap()
+                    .to_utc()
+            )
+        );
+        assert!("tomorrow".parse::<PlaylistPosition>().is_err());
+    }
+
+    #[test]
+    fn find_by_program_date_time() {
+        let segments = segments_with_program_date_time("2026-10-18T20:00:00Z", &[4.0, 4.0, 4.0]);
+        let start = |time| at(time).find_start_index(&segments);
+        let end = |time| at(time).find_end_index(&segments);
+        assert_eq!(start("2026-10-18T19:00:00Z"), SegmentLookup::Found(0));
+        assert_eq!(start("2026-10-18T20:00:05Z"), SegmentLookup::Found(1));
+        assert_eq!(start("2026-10-18T20:00:12Z"), SegmentLookup::Later);
+        assert_eq!(end("2026-10-18T19:00:00Z"), SegmentLookup::Earlier);
+        assert_eq!(end("2026-10-18T20:00:08Z"), SegmentLookup::Found(2));
+        assert_eq!(end("2026-10-18T20:00:12Z"), SegmentLookup::Later);
+    }
+
+    #[test]
+    fn resolve_clip_by_media_sequence() {
+        let reference = MediaPlaylist {
+            media_sequence: 10,
+            segments: vec![
+                MediaSegment {
+                    duration: 4.0,
+                    ..MediaSegment::empty()
+                };
+                3
+            ],
+            ..MediaPlaylist::default()
+        };
+        let clip = Clip::resolve(
+            Some(PlaylistPosition::Offset(5.0)),
+            Some(PlaylistPosition::Offset(-1.0)),
+            &reference,
+        )
+        .unwrap();
+        assert_eq!(clip.start, Some(ClipPoint::MediaSequence(11)));
+        assert_eq!(clip.end, Some(ClipPoint::MediaSequence(12)));
+        // A later rendition playlist with a different window
+        let rendition = MediaPlaylist {
+            media_sequence: 12,
+            ..reference
+        };
+        let start = clip.start.unwrap();
+        assert_eq!(start.find_start_index(&rendition), SegmentLookup::Earlier);
+        assert_eq!(
+            clip.end.unwrap().find_end_index(&rendition),
+            SegmentLookup::Found(0)
+        );
+    }
+
+    #[test]
+    fn find_without_program_date_time() {
+        let segments = vec![MediaSegment::empty(); 3];
+        let position = at("2026-10-18T20:00:00Z");
+        assert_eq!(
+            position.find_start_index(&segments),
+            SegmentLookup::NotFound
+        );
+        assert_eq!(position.find_end_index(&segments), SegmentLookup::NotFound);
+        let playlist = MediaPlaylist {
+            segments,
+            ..MediaPlaylist::default()
+        };
+        assert!(matches!(
+            Clip::resolve(Some(position), None, &playlist),
+            Err(RecordError::Config(_))
+        ));
+        assert!(matches!(
+            Clip::resolve(None, Some(position), &playlist),
+            Err(RecordError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::anyhow;
+use chrono::DateTime;
+use cookie_store::{CookieStore, RawCookie};
+use reqwest::header::HeaderValue;
+use tokio::fs;
+use tracing::debug;
+use url::Url;
+
+use crate::record::RecordError;
+
+/// The cookie jar of a recording, which can be imported from and exported to a file.
+#[derive(Debug, Default)]
+pub(crate) struct CookieJar {
+    store: RwLock<CookieStore>,
+}
+
+impl CookieJar {
+    /// Load cookies from a Netscape `cookies.txt` file,
+    /// or from a JSON file written by [`CookieJar::save`].
+    pub(crate) async fn load(path: &Path) -> Result<Self, RecordError> {
+        let contents = fs::read_to_string(path).await?;
+        let store = if contents.trim_start().starts_with('[') {
+            cookie_store::serde::json::load_all(contents.as_bytes()).map_err(|e| anyhow!("{e}"))
+        } else {
+            parse_netscape_cookies(&contents)
+        }
+        .map_err(|e| {
+            RecordError::Parse(anyhow!(
+                "Error while parsing cookies from {}: {e}",
+                path.display()
+            ))
+        })?;
+        Ok(Self {
+            store: RwLock::new(store),
+        })
+    }
+
+    /// Save all cookies as JSON, including session cookies.
+    pub(crate) async fn save(&self, path: &Path) -> Result<(), RecordError> {
+        let mut buffer = vec![];
+        cookie_store::serde::json::save_incl_expired_and_nonpersistent(
+            &self.store.read().unwrap(),
+            &mut buffer,
+        )
+        .map_err(|e| RecordError::Io(std::io::Error::other(e.to_string())))?;
+        fs::write(path, buffer).await?;
+        Ok(())
+    }
+}
+
+impl reqwest::cookie::CookieStore for CookieJar {
+    fn set_cookies(&self, cookie_headers: &mut dyn Iterator<Item = &HeaderValue>, url: &Url) {
+        let cookies = cookie_headers
+            .filter_map(|header| header.to_str().ok())
+            .filter_map(|header| RawCookie::parse(header.to_string()).ok());
+        self.store
+            .write()
+            .unwrap()
+            .store_response_cookies(cookies, url);
+    }
+
+    fn cookies(&self, url: &Url) -> Option<HeaderValue> {
+        let store = self.store.read().unwrap();
+        let header = store
+            .get_request_values(url)
+            .map(|(name, value)| format!("{name}={value}"))
+            .collect::<Vec<_>>()
+            .join("; ");
+        if header.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&header).ok()
+        }
+    }
+}
+
+/// Parse cookies in the Netscape `cookies.txt` format, as exported by browsers and curl.
+///
+/// Every line contains the domain, whether subdomains are included, the path,
+/// whether the cookie is secure, the expiry time (0 for session cookies), the name and the value,
+/// separated by tabs.
+fn parse_netscape_cookies(contents: &str) -> anyhow::Result<CookieStore> {
+    let mut store = CookieStore::default();
+    for (index, line) in contents.lines().enumerate() {
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(line) => (line, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        let [
+            domain,
+            include_subdomains,
+            path,
+            secure,
+            expires,
+            name,
+            value,
+        ] = fields[..]
+        else {
+            return Err(anyhow!("invalid cookie on line {}", index + 1));
+        };
+        let host = domain.trim_start_matches('.');
+        let mut cookie = format!("{name}={value}; Path={path}");
+        if include_subdomains.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str(&format!("; Domain={host}"));
+        }
+        if secure.eq_ignore_ascii_case("TRUE") {
+            cookie.push_str("; Secure");
+        }
+        if http_only {
+            cookie.push_str("; HttpOnly");
+        }
+        let expires: i64 = expires
+            .parse()
+            .map_err(|_| anyhow!("invalid expiry time on line {}", index + 1))?;
+        if expires != 0
+            && let Some(expires) = DateTime::from_timestamp(expires, 0)
+        {
+            cookie.push_str(
+                &expires
+                    .format("; Expires=%a, %d %b %Y %H:%M:%S GMT")
+                    .to_string(),
+            );
+        }
+        let url = Url::parse(&format!("https://{host}{path}"))
+            .map_err(|_| anyhow!("invalid domain on line {}", index + 1))?;
+        if let Err(e) = store.parse(&cookie, &url) {
+            debug!("Skipping cookie {name} for {domain}: {e}");
+        }
+    }
+    Ok(store)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_netscape() {
+        let store = parse_netscape_cookies(
+            "# Netscape HTTP Cookie File\n\
+             .cdn.example.com\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+             #HttpOnly_origin.example.com\tFALSE\t/live\tFALSE\t4102444800\ttoken\txyz\n\
+             expired.example.com\tFALSE\t/\tFALSE\t1\told\tvalue\n",
+        )
+        .unwrap();
+        let values = |url: &str| -> Vec<String> {
+            store
+                .get_request_values(&Url::parse(url).unwrap())
+                .map(|(name, value)| format!("{name}={value}"))
+                .collect()
+        };
+        assert_eq!(values("https://a.cdn.example.com/seg.ts"), ["session=abc"]);
+        assert!(values("http://a.cdn.example.com/seg.ts").is_empty());
+        assert_eq!(
+            values("http://origin.example.com/live/a.m3u8"),
+            ["token=xyz"]
+        );
+        assert!(values("http://origin.example.com/vod/a.m3u8").is_empty());
+        assert!(values("https://expired.example.com/").is_empty());
+        assert!(parse_netscape_cookies("a.com\tTRUE\t/\n").is_err());
+    }
+}
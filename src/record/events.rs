@@ -0,0 +1,292 @@
+use chrono::{DateTime, FixedOffset};
+use m3u8_rs::{Key, MediaPlaylist};
+use serde::Serialize;
+use tokio::sync::broadcast;
+use url::Url;
+
+use crate::record::Anomaly;
+use crate::record::rewrite::stable_url;
+use crate::shared::StopReason;
+
+/// The number of events kept for subscribers that fall behind.
+const EVENT_CAPACITY: usize = 256;
+
+/// Something that happened while recording, see [`Recorder::subscribe`].
+///
+/// [`Recorder::subscribe`]: crate::record::Recorder::subscribe
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[non_exhaustive]
+pub enum RecordEvent {
+    /// A media playlist was fetched and written to the recording.
+    PlaylistFetched {
+        /// The name of the media playlist in the recording.
+        playlist: String,
+        /// The path of the written snapshot, relative to the recording.
+        path: String,
+        media_sequence: u64,
+        segments: usize,
+        ended: bool,
+    },
+    /// A segment of a media playlist was downloaded.
+    SegmentDownloaded {
+        /// The name of the media playlist in the recording.
+        playlist: String,
+        /// The file name, relative to the directory of the media playlist.
+        file_name: String,
+        /// The size of the segment, in bytes.
+        size: u64,
+    },
+    /// A new segment starts with `#EXT-X-DISCONTINUITY`.
+    Discontinuity {
+        playlist: String,
+        media_sequence: u64,
+    },
+    /// A new segment has an `#EXT-X-DATERANGE`, such as an ad marker.
+    DateRange {
+        playlist: String,
+        media_sequence: u64,
+        id: String,
+        class: Option<String>,
+        start_date: DateTime<FixedOffset>,
+        end_date: Option<DateTime<FixedOffset>>,
+        duration: Option<f64>,
+        planned_duration: Option<f64>,
+    },
+    /// A new segment is encrypted with a different key than the previous segment.
+    KeyRotation {
+        playlist: String,
+        media_sequence: u64,
+        method: String,
+        /// The original URI of the new key.
+        uri: Option<String>,
+    },
+    /// A media playlist of a variant stream or rendition disappeared from the server.
+    ///
+    /// This is only sent after retrying a few times, and the recording then stops with an error.
+    PlaylistGone {
+        playlist: String,
+        /// The HTTP status code returned for the playlist.
+        status: u16,
+    },
+    /// A media playlist changed in an unexpected way since its previous snapshot.
+    Anomaly { playlist: String, anomaly: Anomaly },
+    /// A playlist or segment of a media playlist could not be downloaded.
+    DownloadError { playlist: String, message: String },
+    /// A media playlist stopped being recorded, because the stream ended
+    /// or because of a limit such as `stop_at` or `max_size`.
+    PlaylistEnded { playlist: String },
+    /// The recording stopped because of an error.
+    Error { message: String },
+    /// The recording stopped. This is always the last event.
+    Stopped { reason: StopReason },
+}
+
+/// Sends [`RecordEvent`]s to all subscribers.
+///
+/// This is cheap to clone, all clones send to the same subscribers.
+#[derive(Debug, Clone)]
+pub(crate) struct RecordEvents {
+    sender: broadcast::Sender<RecordEvent>,
+}
+
+impl RecordEvents {
+    pub(crate) fn new() -> Self {
+        Self {
+            sender: broadcast::Sender::new(EVENT_CAPACITY),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<RecordEvent> {
+        self.sender.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: RecordEvent) {
+        // Fails if nobody is subscribed, which is fine
+        let _ = self.sender.send(event);
+    }
+}
+
+/// Find the events for the new segments of a media playlist,
+/// i.e. the segments starting from `next_media_sequence`.
+///
+/// `current_key` is the key of the last segment seen so far, and is updated
+/// to detect key rotations across playlist refreshes. Key URIs are resolved against `url`,
+/// and compared without their `volatile_query_params` (such as auth tokens),
+/// so a refreshed token is not reported as a key rotation.
+pub(crate) fn segment_events(
+    playlist_name: &str,
+    url: &Url,
+    volatile_query_params: &[String],
+    playlist: &MediaPlaylist,
+    next_media_sequence: u64,
+    current_key: &mut Option<Key>,
+) -> Vec<RecordEvent> {
+    let mut events = Vec::new();
+    // The key applies to all following segments, until the next key
+    let mut key = None;
+    for (media_sequence, segment) in (playlist.media_sequence..).zip(&playlist.segments) {
+        if segment.key.is_some() {
+            key = segment.key.as_ref();
+        }
+        if media_sequence < next_media_sequence {
+            continue;
+        }
+        if segment.discontinuity {
+            events.push(RecordEvent::Discontinuity {
+                playlist: playlist_name.to_string(),
+                media_sequence,
+            });
+        }
+        if let Some(date_range) = &segment.daterange {
+            events.push(RecordEvent::DateRange {
+                playlist: playlist_name.to_string(),
+                media_sequence,
+                id: date_range.id.clone(),
+                class: date_range.class.clone(),
+                start_date: date_range.start_date,
+                end_date: date_range.end_date,
+                duration: date_range.duration,
+                planned_duration: date_range.planned_duration,
+            });
+        }
+        if let Some(key) = key
+            && let stable_key = stable_key(key, url, volatile_query_params)
+            && current_key.as_ref() != Some(&stable_key)
+        {
+            // Only report a change of key, not the first key of the recording
+            if current_key.is_some() {
+                events.push(RecordEvent::KeyRotation {
+                    playlist: playlist_name.to_string(),
+                    media_sequence,
+                    method: key.method.to_string(),
+                    uri: key.uri.clone(),
+                });
+            }
+            *current_key = Some(stable_key);
+        }
+    }
+    events
+}
+
+/// A key with a resolved URI, without any volatile query parameters.
+fn stable_key(key: &Key, url: &Url, volatile_query_params: &[String]) -> Key {
+    let uri = key.uri.as_ref().map(|uri| match url.join(uri) {
+        Ok(key_url) => stable_url(&key_url, volatile_query_params).to_string(),
+        Err(_) => uri.clone(),
+    });
+    Key { uri, ..key.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m3u8_rs::{KeyMethod, MediaSegment};
+
+    fn key(uri: &str) -> Option<Key> {
+        Some(Key {
+            method: KeyMethod::AES128,
+            uri: Some(uri.to_string()),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn events_for_new_segments() {
+        let playlist = MediaPlaylist {
+            media_sequence: 10,
+            segments: vec![
+                MediaSegment {
+                    key: key("key1"),
+                    discontinuity: true,
+                    ..Default::default()
+                },
+                MediaSegment::default(),
+                MediaSegment {
+                    discontinuity: true,
+                    ..Default::default()
+                },
+                MediaSegment {
+                    key: key("key2"),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let url = Url::parse("https://a.com/live/index.m3u8").unwrap();
+        let mut current_key = None;
+        let events = segment_events("index.m3u8", &url, &[], &playlist, 11, &mut current_key);
+        assert_eq!(
+            events,
+            [
+                RecordEvent::Discontinuity {
+                    playlist: "index.m3u8".to_string(),
+                    media_sequence: 12,
+                },
+                RecordEvent::KeyRotation {
+                    playlist: "index.m3u8".to_string(),
+                    media_sequence: 13,
+                    method: "AES-128".to_string(),
+                    uri: Some("key2".to_string()),
+                },
+            ]
+        );
+        assert_eq!(current_key, key("https://a.com/live/key2"));
+
+        // Same key in the next refresh
+        let events = segment_events("index.m3u8", &url, &[], &playlist, 14, &mut current_key);
+        assert_eq!(events, []);
+    }
+
+    #[test]
+    fn key_with_new_token_is_not_rotated() {
+        let playlist = |key_uri: &str, media_sequence: u64| MediaPlaylist {
+            media_sequence,
+            segments: vec![MediaSegment {
+                key: key(key_uri),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let url = Url::parse("https://a.com/live/index.m3u8?token=1").unwrap();
+        let volatile_query_params = ["token".to_string()];
+        let mut current_key = None;
+        let first = playlist("key1?id=1&token=1", 0);
+        segment_events(
+            "index.m3u8",
+            &url,
+            &volatile_query_params,
+            &first,
+            0,
+            &mut current_key,
+        );
+        let refreshed = playlist("key1?id=1&token=2", 1);
+        let events = segment_events(
+            "index.m3u8",
+            &url,
+            &volatile_query_params,
+            &refreshed,
+            1,
+            &mut current_key,
+        );
+        assert_eq!(events, []);
+        let rotated = playlist("key1?id=2&token=2", 2);
+        let events = segment_events(
+            "index.m3u8",
+            &url,
+            &volatile_query_params,
+            &rotated,
+            2,
+            &mut current_key,
+        );
+        assert_eq!(
+            events,
+            [RecordEvent::KeyRotation {
+                playlist: "index.m3u8".to_string(),
+                media_sequence: 2,
+                method: "AES-128".to_string(),
+                uri: Some("key1?id=2&token=2".to_string()),
+            }]
+        );
+    }
+}
@@ -0,0 +1,148 @@
+use std::process::Stdio;
+use std::time::Duration;
+
+use reqwest::Client;
+use reqwest::header::CONTENT_TYPE;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+use url::Url;
+
+use crate::record::auth::shell_command;
+use crate::record::{RecordEvent, RecordEvents};
+
+/// The timeout for delivering a single event to a webhook.
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long to wait for the hooks to handle the remaining events when the recording stops.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An action to run for every [`RecordEvent`] of a recording.
+///
+/// Events are delivered one at a time, in order. Failures are logged,
+/// but don't stop the recording.
+#[derive(Debug, Clone)]
+pub enum EventHook {
+    /// POST every event as JSON to this URL.
+    Webhook(Url),
+    /// Run this command for every event, interpreted by the system shell.
+    ///
+    /// The event is written as JSON to its stdin,
+    /// and its type is set in the `STREAMRR_EVENT` environment variable.
+    Command(String),
+}
+
+impl EventHook {
+    async fn run(&self, client: &Client, event: &RecordEvent) -> anyhow::Result<()> {
+        let json = serde_json::to_value(event)?;
+        match self {
+            EventHook::Webhook(url) => {
+                client
+                    .post(url.clone())
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(json.to_string())
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            EventHook::Command(command) => {
+                let mut child = shell_command(command)
+                    .env("STREAMRR_EVENT", json["type"].as_str().unwrap_or_default())
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .kill_on_drop(true)
+                    .spawn()?;
+                let mut stdin = child.stdin.take().unwrap();
+                // The command may not read its stdin at all
+                let _ = stdin.write_all(format!("{json}\n").as_bytes()).await;
+                drop(stdin);
+                let status = child.wait().await?;
+                if !status.success() {
+                    anyhow::bail!("{command} failed with {status}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The running hooks of a recording.
+pub(crate) struct HookTasks {
+    tasks: JoinSet<usize>,
+    token: CancellationToken,
+}
+
+impl HookTasks {
+    /// Run every hook for all events, until the recording stops.
+    pub(crate) fn spawn(hooks: &[EventHook], events: &RecordEvents) -> Self {
+        let token = CancellationToken::new();
+        let mut tasks = JoinSet::new();
+        for hook in hooks {
+            let hook = hook.clone();
+            let receiver = events.subscribe();
+            tasks.spawn(run_hook(hook, receiver, token.clone()));
+        }
+        Self { tasks, token }
+    }
+
+    /// Wait for the hooks to handle the remaining events after the recording stopped.
+    ///
+    /// Hooks that are still busy after a timeout are stopped, dropping their remaining events.
+    pub(crate) async fn finish(mut self) {
+        let mut dropped = 0;
+        let finish_all = async {
+            while let Some(result) = self.tasks.join_next().await {
+                dropped += result.unwrap_or_default();
+            }
+        };
+        if timeout(SHUTDOWN_TIMEOUT, finish_all).await.is_err() {
+            self.token.cancel();
+            while let Some(result) = self.tasks.join_next().await {
+                dropped += result.unwrap_or_default();
+            }
+        }
+        if dropped > 0 {
+            warn!("Event hooks did not finish in time, dropped {dropped} events");
+        }
+    }
+}
+
+/// Run the hook for every event, until the recording stops or `token` is cancelled.
+///
+/// Returns the number of events that were dropped because of the cancellation.
+async fn run_hook(
+    hook: EventHook,
+    mut receiver: broadcast::Receiver<RecordEvent>,
+    token: CancellationToken,
+) -> usize {
+    let client = Client::builder()
+        .timeout(WEBHOOK_TIMEOUT)
+        .build()
+        .unwrap_or_default();
+    loop {
+        let event = match token.run_until_cancelled(receiver.recv()).await {
+            Some(Ok(event)) => event,
+            Some(Err(RecvError::Lagged(count))) => {
+                warn!(
+                    ?hook,
+                    "Skipped {count} events, because the hook is too slow"
+                );
+                continue;
+            }
+            Some(Err(RecvError::Closed)) => return 0,
+            None => return receiver.len(),
+        };
+        debug!(?hook, ?event, "Running event hook");
+        match token.run_until_cancelled(hook.run(&client, &event)).await {
+            Some(Ok(())) => {}
+            Some(Err(e)) => warn!(?hook, "Event hook failed: {e}"),
+            None => return receiver.len() + 1,
+        }
+        if matches!(event, RecordEvent::Stopped { .. }) {
+            return 0;
+        }
+    }
+}
@@ -3,38 +3,171 @@ use chrono::{DateTime, Utc};
 use futures::future::{BoxFuture, FutureExt, TryFutureExt};
 use futures::stream::{StreamExt, TryStreamExt, iter};
 use m3u8_rs::*;
-use reqwest::header::HeaderMap;
-use reqwest::{Client, Response};
+use reqwest::StatusCode;
+use reqwest::header::{HeaderMap, HeaderValue, RANGE, SET_COOKIE};
+use serde_json::json;
 use std::io;
+use std::num::NonZeroUsize;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::time::{Duration, Instant};
 use tokio::fs;
-use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use tokio::task::JoinSet;
-use tokio::time::sleep_until;
+use tokio::time::{sleep, sleep_until};
 use tokio_util::io::StreamReader;
 use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 use url::Url;
 
-use crate::shared::{ByteRange, MediaSelect, Recording, StripBom, VariantSelectOptions};
+use crate::shared::{
+    ByteRange, FileRecord, HostInfo, MediaSelect, Recording, RecordingMetadata, StopReason,
+    StripBom, VariantSelectOptions,
+};
+pub use anomaly::Anomaly;
+use anomaly::{AnomalyDetector, report_anomalies};
+use auth::Authenticator;
+pub use auth::{AuthParams, AuthSource, CommandAuthSource, EndpointAuthSource};
+use client::{HttpClient, build_client};
+pub use clip::*;
+use cookies::CookieJar;
+pub use events::RecordEvent;
+use events::{RecordEvents, segment_events};
+pub use hooks::EventHook;
+use hooks::HookTasks;
+pub use progress::*;
+pub use recorder::{Recorder, RecorderHandle};
+pub use recover::recover;
 pub use rewrite::*;
+use window::RollingWindow;
 
+mod anomaly;
+mod auth;
+mod client;
+mod clip;
+mod cookies;
+mod events;
+mod hooks;
+mod progress;
+mod recorder;
+mod recover;
 mod rewrite;
+mod window;
 
-const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+const MASTER_PLAYLIST_NAME: &str = "index.m3u8";
+const DEFAULT_CONCURRENT_DOWNLOADS: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+const DEFAULT_AUTH_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often to retry a media playlist that returns 404 or 410, before giving up.
+const PLAYLIST_GONE_RETRIES: u32 = 3;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Default, Clone)]
 pub struct RecordOptions {
     pub variant_select: VariantSelectOptions,
     pub audio: MediaSelect,
     pub video: MediaSelect,
     pub subtitle: MediaSelect,
-    pub start: Option<f32>,
-    pub end: Option<f32>,
+    pub start: Option<PlaylistPosition>,
+    pub end: Option<PlaylistPosition>,
     pub headers: HeaderMap,
     pub keep_names: bool,
+    /// The wall-clock time at which to start recording.
+    pub start_at: Option<DateTime<Utc>>,
+    /// The wall-clock time at which to stop recording.
+    pub stop_at: Option<DateTime<Utc>>,
+    /// The maximum duration of the recording, counted from when recording starts.
+    pub duration: Option<Duration>,
+    /// The maximum number of segments to record for each media playlist.
+    pub max_segments: Option<u64>,
+    /// The maximum size of the recording on disk, in bytes.
+    pub max_size: Option<u64>,
+    /// Only keep the snapshots and segments of the last part of the recording.
+    pub rolling_window: Option<Duration>,
+    /// The maximum number of concurrent downloads for each media playlist.
+    /// Defaults to 4.
+    pub concurrent_downloads: Option<NonZeroUsize>,
+    /// The maximum number of concurrent connections for the whole recording.
+    pub max_connections: Option<NonZeroUsize>,
+    /// The maximum number of concurrent connections to a single host.
+    pub max_connections_per_host: Option<NonZeroUsize>,
+    /// The URL of an HTTP, HTTPS or SOCKS proxy to use for all requests.
+    pub proxy: Option<Url>,
+    /// Extra root CA certificates to trust, as paths to PEM bundles.
+    pub ca_certs: Vec<PathBuf>,
+    /// The path to a PEM client certificate, for mutual TLS.
+    ///
+    /// The file must also contain the private key, unless `client_key` is given.
+    pub client_cert: Option<PathBuf>,
+    /// The path to the PEM private key of the client certificate.
+    pub client_key: Option<PathBuf>,
+    /// Accept invalid TLS certificates and host names. This is dangerous!
+    pub insecure: bool,
+    /// The timeout for connecting to a server.
+    pub connect_timeout: Option<Duration>,
+    /// The timeout for reading a response, reset after every successful read.
+    pub read_timeout: Option<Duration>,
+    /// The `User-Agent` header to send with every request.
+    pub user_agent: Option<String>,
+    /// The source of authentication parameters to add to every request URL.
+    pub auth: Option<Arc<dyn AuthSource>>,
+    /// How often to refresh the authentication parameters.
+    ///
+    /// If not set, they are only refreshed when a request is rejected.
+    pub auth_refresh_interval: Option<Duration>,
+    /// How long to wait for new authentication parameters.
+    ///
+    /// Defaults to 30 seconds.
+    pub auth_timeout: Option<Duration>,
+    /// A file with cookies to send, either in the Netscape `cookies.txt` format
+    /// or in the JSON format written by `save_cookies`.
+    pub cookies: Option<PathBuf>,
+    /// Save all cookies to `cookies.json` in the recording directory when the recording stops.
+    pub save_cookies: bool,
+    /// Actions to run for every event of the recording.
+    pub event_hooks: Vec<EventHook>,
+    /// Refresh live playlists at this fixed interval.
+    ///
+    /// If not set, the reload timing of the HLS specification is used.
+    pub poll_interval: Option<Duration>,
+}
+
+impl RecordOptions {
+    /// A summary of the options, to store in the metadata of the recording.
+    ///
+    /// Options that may contain secrets, such as headers, are left out.
+    fn metadata(&self) -> serde_json::Value {
+        let format_duration = |duration: Option<Duration>| {
+            duration.map(|d| humantime::format_duration(d).to_string())
+        };
+        json!({
+            "variant": self.variant_select,
+            "audio": self.audio,
+            "video": self.video,
+            "subtitle": self.subtitle,
+            "start": self.start,
+            "end": self.end,
+            "keep_names": self.keep_names,
+            "start_at": self.start_at,
+            "stop_at": self.stop_at,
+            "duration": format_duration(self.duration),
+            "max_segments": self.max_segments,
+            "max_size": self.max_size,
+            "rolling_window": format_duration(self.rolling_window),
+            "concurrent_downloads": self.concurrent_downloads,
+            "poll_interval": format_duration(self.poll_interval),
+            "user_agent": self.user_agent,
+        })
+    }
+
+    /// The time at which to stop recording, if the recording starts at `start_time`.
+    fn stop_time(&self, start_time: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let end_of_duration = self
+            .duration
+            .and_then(|duration| chrono::Duration::from_std(duration).ok())
+            .and_then(|duration| start_time.checked_add_signed(duration));
+        [self.stop_at, end_of_duration].into_iter().flatten().min()
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -48,31 +181,143 @@ pub enum RecordError {
     Rewrite(#[from] RewriteError),
     #[error("I/O error: {0}")]
     Io(#[from] io::Error),
+    #[error("HTTP error: {status} for {url}")]
+    Http { url: Url, status: StatusCode },
+    #[error("authentication error: {0}")]
+    Auth(#[source] anyhow::Error),
     #[error("cancelled")]
     Cancelled,
 }
 
+/// State shared between all media playlists of a recording.
+#[derive(Clone)]
+struct RecordState {
+    recording: Arc<Mutex<RecordingFile>>,
+    /// The size of the recording on disk, in bytes
+    size: Arc<AtomicU64>,
+    progress: RecordProgress,
+    events: RecordEvents,
+}
+
+/// Record the stream at `url` to the directory `dest`, until it ends or `token` is cancelled.
+///
+/// Use a [`Recorder`] to also receive events while recording.
 pub async fn record(
     url: &Url,
     dest: &Path,
     options: RecordOptions,
+    progress: RecordProgress,
     token: CancellationToken,
 ) -> Result<(), RecordError> {
+    record_with_events(url, dest, options, progress, RecordEvents::new(), token).await
+}
+
+async fn record_with_events(
+    url: &Url,
+    dest: &Path,
+    options: RecordOptions,
+    progress: RecordProgress,
+    events: RecordEvents,
+    token: CancellationToken,
+) -> Result<(), RecordError> {
+    let cookie_jar = match &options.cookies {
+        Some(path) => CookieJar::load(path).await?,
+        None => CookieJar::default(),
+    };
+    let cookie_jar = Arc::new(cookie_jar);
+    let save_cookies = options.save_cookies;
     fs::create_dir_all(dest).await?;
-    let recording_path = dest.join("recording.json");
-    let recording = RecordingFile::new(&recording_path).await?;
-    let recording = Arc::new(Mutex::new(recording));
-    let client = Client::builder()
-        .cookie_store(true)
-        .default_headers(options.headers.clone())
-        .build()
-        .map_err(|_| RecordError::Config("Error while building HTTP client"))?;
+    let recording = RecordingFile::new(&dest.join("recording.json")).await?;
+    let state = RecordState {
+        recording: Arc::new(Mutex::new(recording)),
+        size: Arc::new(AtomicU64::new(0)),
+        progress: progress.clone(),
+        events: events.clone(),
+    };
+    let hooks = HookTasks::spawn(&options.event_hooks, &events);
+    let result = record_stream(url, dest, options, cookie_jar.clone(), state.clone(), token).await;
+    let stop_reason = match &result {
+        Ok(()) => StopReason::Ended,
+        Err(RecordError::Cancelled) => StopReason::Cancelled,
+        Err(_) => StopReason::Failed,
+    };
+    // Save why the recording stopped
+    {
+        let mut recording = state.recording.lock().await;
+        let metadata = recording.metadata_mut();
+        metadata.stopped = Some(Utc::now());
+        metadata.stop_reason = Some(stop_reason);
+        metadata.error = result.as_ref().err().map(|e| e.to_string());
+        if let Err(e) = recording.save().await {
+            warn!("Error while saving recording: {e}");
+        }
+    }
+    if save_cookies && let Err(e) = cookie_jar.save(&dest.join("cookies.json")).await {
+        warn!("Error while saving cookies: {e}");
+    }
+    progress.log_summary();
+    if let Err(e) = &result
+        && !matches!(e, RecordError::Cancelled)
+    {
+        events.emit(RecordEvent::Error {
+            message: e.to_string(),
+        });
+    }
+    events.emit(RecordEvent::Stopped {
+        reason: stop_reason,
+    });
+    // Let the hooks handle the remaining events
+    hooks.finish().await;
+    result
+}
+
+async fn record_stream(
+    url: &Url,
+    dest: &Path,
+    mut options: RecordOptions,
+    cookie_jar: Arc<CookieJar>,
+    state: RecordState,
+    token: CancellationToken,
+) -> Result<(), RecordError> {
+    // Wait until the scheduled start time (if given)
+    if let Some(start_at) = options.start_at {
+        info!("Waiting until {start_at} to start recording");
+        token
+            .run_until_cancelled(sleep_until(instant_at(start_at).into()))
+            .await
+            .ok_or(RecordError::Cancelled)?;
+    }
+    options.stop_at = options.stop_time(Utc::now());
+    {
+        let mut recording = state.recording.lock().await;
+        *recording.metadata_mut() = RecordingMetadata {
+            source_url: Some(url.to_string()),
+            streamrr_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            options: options.metadata(),
+            started: Some(Utc::now()),
+            host: Some(HostInfo::current()),
+            ..Default::default()
+        };
+        recording.save().await?;
+    }
+    let client = HttpClient::new(
+        build_client(&options, cookie_jar).await?,
+        options.max_connections,
+        options.max_connections_per_host,
+        options.auth.clone().map(|source| {
+            Authenticator::new(
+                source,
+                options.auth_refresh_interval,
+                options.auth_timeout.unwrap_or(DEFAULT_AUTH_TIMEOUT),
+            )
+        }),
+    );
     // Download initial playlist
-    let raw_playlist = token
+    let (raw_playlist, headers) = token
         .run_until_cancelled(download_playlist(&client, url))
         .await
-        .ok_or(RecordError::Cancelled)??
-        .strip_bom();
+        .ok_or(RecordError::Cancelled)??;
+    let raw_playlist = raw_playlist.strip_bom();
     let initial_playlist = parse_playlist_res(raw_playlist.as_bytes()).map_err(|e| {
         RecordError::Parse(anyhow!(
             "Error while parsing playlist: {}",
@@ -82,27 +327,26 @@ pub async fn record(
     match initial_playlist {
         Playlist::MasterPlaylist(master_playlist) => {
             // Master playlist
-            record_master_playlist(
-                &client,
-                url,
-                dest,
-                recording,
-                options,
-                master_playlist,
-                token,
-            )
-            .await?;
+            state
+                .recording
+                .lock()
+                .await
+                .add_file(MASTER_PLAYLIST_NAME.to_string(), file_record(&headers));
+            record_master_playlist(&client, url, dest, state, options, master_playlist, token)
+                .await?;
         }
         Playlist::MediaPlaylist(media_playlist) => {
             // Media playlist only
+            let clip = Clip::resolve(options.start, options.end, &media_playlist)?;
             record_media_playlist(
                 &client,
                 url,
                 "",
-                Some(media_playlist),
+                Some((media_playlist, headers)),
                 dest,
-                recording,
+                state,
                 options,
+                clip,
                 token,
             )
             .await?;
@@ -111,11 +355,12 @@ pub async fn record(
     Ok(())
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn record_master_playlist(
-    client: &Client,
+    client: &HttpClient,
     url: &Url,
     dest: &Path,
-    recording: Arc<Mutex<RecordingFile>>,
+    state: RecordState,
     options: RecordOptions,
     mut master_playlist: MasterPlaylist,
     token: CancellationToken,
@@ -179,16 +424,28 @@ async fn record_master_playlist(
 
     let master_playlist = master_playlist;
 
+    // Resolve the start and end against the first variant stream,
+    // so all variant streams and renditions start and end on aligned segments
+    let mut first_variant_playlist = None;
+    let mut clip = Clip::default();
+    if (options.start.is_some() || options.end.is_some())
+        && let Some(first_variant_url) = master_playlist.variants.first().and_then(original_url)
+    {
+        let playlist = token
+            .run_until_cancelled(download_media_playlist(client, &first_variant_url))
+            .await
+            .ok_or(RecordError::Cancelled)??;
+        clip = Clip::resolve(options.start, options.end, &playlist.0)?;
+        first_variant_playlist = Some(playlist);
+    }
+
     // Start recording selected variant streams and renditions
     let mut join_set = JoinSet::new();
     for variant in &master_playlist.variants {
-        let Some(other_attributes) = &variant.other_attributes else {
-            continue;
-        };
-        let Some(variant_url) = other_attributes.get(ORIGINAL_URI) else {
+        let Some(variant_url) = original_url(variant) else {
             continue;
         };
-        let variant_url = Url::parse(variant_url.as_str()).unwrap();
+        let initial_playlist = first_variant_playlist.take();
         let variant_dir = Path::new(&variant.uri)
             .parent()
             .unwrap()
@@ -196,7 +453,7 @@ async fn record_master_playlist(
             .to_string();
         let client = client.clone();
         let dest = PathBuf::from(dest);
-        let recording = recording.clone();
+        let state = state.clone();
         let options = options.clone();
         let token = token.clone();
         join_set.spawn(async move {
@@ -204,10 +461,11 @@ async fn record_master_playlist(
                 &client,
                 &variant_url,
                 &variant_dir,
-                None,
+                initial_playlist,
                 &dest,
-                recording,
+                state,
                 options,
+                clip,
                 token,
             )
             .await
@@ -231,20 +489,21 @@ async fn record_master_playlist(
             .to_string();
         let client = client.clone();
         let dest = PathBuf::from(dest);
-        let recording = recording.clone();
+        let state = state.clone();
         let options = options.clone();
         let token = token.clone();
         join_set.spawn(async move {
             record_media_playlist(
-                &client, &media_url, &media_dir, None, &dest, recording, options, token,
+                &client, &media_url, &media_dir, None, &dest, state, options, clip, token,
             )
             .await
         });
     }
     // Write updated master playlist
-    let master_name = "index.m3u8";
+    let master_name = MASTER_PLAYLIST_NAME;
     write_master_playlist(&dest.join(master_name), &master_playlist).await?;
-    recording
+    state
+        .recording
         .lock()
         .await
         .add_and_save(Utc::now(), master_name, master_name.to_string())
@@ -258,41 +517,107 @@ async fn record_master_playlist(
 
 #[allow(clippy::too_many_arguments)]
 async fn record_media_playlist(
-    client: &Client,
+    client: &HttpClient,
     url: &Url,
     dir: &str,
-    mut initial_playlist: Option<MediaPlaylist>,
+    mut initial_playlist: Option<(MediaPlaylist, HeaderMap)>,
     dest: &Path,
-    recording: Arc<Mutex<RecordingFile>>,
-    mut options: RecordOptions,
+    state: RecordState,
+    options: RecordOptions,
+    mut clip: Clip,
     token: CancellationToken,
 ) -> Result<(), RecordError> {
     let dest_dir = dest.join(dir);
     fs::create_dir_all(&dest_dir).await?;
     let mut rewriter = Rewriter::new(url, dir.as_ref(), options.keep_names);
+    let volatile_query_params = client.volatile_query_params().await;
+    rewriter.set_volatile_query_params(volatile_query_params.clone());
     let name_in_recording = rewriter.playlist_path();
-    let mut previous_playlist = None;
+    let mut previous_playlist: Option<MediaPlaylist> = None;
     let mut lowest_media_sequence = 0;
     let mut highest_media_sequence = None;
+    let mut max_segments = options.max_segments;
+    let mut rolling_window = options.rolling_window.map(RollingWindow::new);
+    let mut current_key = None;
+    let mut anomaly_detector = AnomalyDetector::new(url, volatile_query_params.clone());
+    let mut previous_end_media_sequence = None;
+    let mut gone_retries = 0;
+    let mut retry_delay = Duration::ZERO;
     loop {
         // Download and rewrite playlist
-        let mut media_playlist = if let Some(playlist) = initial_playlist.take() {
+        let load_start = Instant::now();
+        let (mut media_playlist, headers) = if let Some(playlist) = initial_playlist.take() {
             playlist
         } else {
-            let raw_playlist = token
-                .run_until_cancelled(download_playlist(client, url))
+            match token
+                .run_until_cancelled(download_media_playlist(client, url))
                 .await
-                .ok_or(RecordError::Cancelled)??
-                .strip_bom();
-            parse_media_playlist_res(raw_playlist.as_bytes()).map_err(|e| {
-                RecordError::Parse(anyhow!(
-                    "Error while parsing media playlist: {}",
-                    e.map_input(|i| String::from_utf8_lossy(i))
-                ))
-            })?
+                .ok_or(RecordError::Cancelled)?
+            {
+                Ok(playlist) => {
+                    gone_retries = 0;
+                    playlist
+                }
+                Err(RecordError::Http { status, .. })
+                    if is_gone(status) && gone_retries < PLAYLIST_GONE_RETRIES =>
+                {
+                    // The server may be updating the playlist, so try again a few times
+                    gone_retries += 1;
+                    warn!(
+                        playlist = name_in_recording,
+                        %status,
+                        "Playlist not found, retrying ({gone_retries}/{PLAYLIST_GONE_RETRIES}): {url}"
+                    );
+                    token
+                        .run_until_cancelled(sleep(retry_delay))
+                        .await
+                        .ok_or(RecordError::Cancelled)?;
+                    continue;
+                }
+                Err(e) => {
+                    if let RecordError::Http { status, .. } = &e
+                        && is_gone(*status)
+                    {
+                        warn!(
+                            playlist = name_in_recording,
+                            %status,
+                            "Playlist disappeared: {url}"
+                        );
+                        state.events.emit(RecordEvent::PlaylistGone {
+                            playlist: name_in_recording.clone(),
+                            status: status.as_u16(),
+                        });
+                    }
+                    state.events.emit(RecordEvent::DownloadError {
+                        playlist: name_in_recording.clone(),
+                        message: e.to_string(),
+                    });
+                    return Err(e);
+                }
+            }
         };
-        let now = Instant::now();
         let playlist_time = Utc::now();
+        let end_media_sequence =
+            media_playlist.media_sequence + (media_playlist.segments.len() as u64);
+        let refresh_delay = refresh_delay(
+            &media_playlist,
+            previous_end_media_sequence != Some(end_media_sequence),
+            options.poll_interval,
+        );
+        previous_end_media_sequence = Some(end_media_sequence);
+        retry_delay = refresh_delay;
+        // Compare with the previous playlist as downloaded, before clipping and rewriting
+        let anomalies = anomaly_detector.check(&media_playlist, playlist_time);
+        if !anomalies.is_empty() {
+            for anomaly in &anomalies {
+                warn!(playlist = name_in_recording, "Anomaly: {anomaly}");
+                state.events.emit(RecordEvent::Anomaly {
+                    playlist: name_in_recording.clone(),
+                    anomaly: anomaly.clone(),
+                });
+            }
+            report_anomalies(dest, &name_in_recording, playlist_time, &anomalies).await?;
+        }
         let file_name = if previous_playlist.is_none() && media_playlist.end_list {
             // Playlist is a VOD. No need for a timestamp, since we won't ever refresh it.
             rewriter.playlist_path()
@@ -300,43 +625,234 @@ async fn record_media_playlist(
             // Playlist is live, or was live and has now ended
             rewriter.playlist_path_with_timestamp(&playlist_time)
         };
-        // Clip to start and end time (if given)
-        if let Some(start) = options.start.take()
-            && let Some(start_index) = find_segment_index_by_offset(&media_playlist.segments, start)
-        {
-            lowest_media_sequence = media_playlist.media_sequence + (start_index as u64)
+        // Clip to start and end point (if given)
+        if let Some(start) = clip.start.take() {
+            match start.find_start_index(&media_playlist) {
+                SegmentLookup::Found(start_index) => {
+                    lowest_media_sequence = media_playlist.media_sequence + (start_index as u64)
+                }
+                SegmentLookup::Later => {
+                    // Skip all segments, and look again in the next playlist
+                    lowest_media_sequence =
+                        media_playlist.media_sequence + (media_playlist.segments.len() as u64);
+                    clip.start = Some(start);
+                }
+                SegmentLookup::Earlier => {}
+                SegmentLookup::NotFound => {
+                    return Err(RecordError::Config(MISSING_START_PROGRAM_DATE_TIME));
+                }
+            }
+        }
+        if let Some(end) = clip.end.take() {
+            match end.find_end_index(&media_playlist) {
+                SegmentLookup::Found(end_index) => {
+                    let end_media_sequence = media_playlist.media_sequence + (end_index as u64);
+                    highest_media_sequence = Some(
+                        highest_media_sequence.map_or(end_media_sequence, |highest: u64| {
+                            highest.min(end_media_sequence)
+                        }),
+                    );
+                }
+                SegmentLookup::Earlier => {
+                    // Already past the end, nothing left to record
+                    media_playlist.segments.clear();
+                    media_playlist.end_list = true;
+                }
+                SegmentLookup::Later => {
+                    // Keep all segments, and look again in the next playlist
+                    clip.end = Some(end);
+                }
+                SegmentLookup::NotFound => {
+                    return Err(RecordError::Config(MISSING_END_PROGRAM_DATE_TIME));
+                }
+            }
         }
-        if let Some(end) = options.end.take()
-            && let Some(end_index) = find_segment_index_by_offset(&media_playlist.segments, end)
+        // Limit the number of segments (if given), once the start is known
+        if clip.start.is_none()
+            && let Some(max_segments) = max_segments.take()
         {
-            highest_media_sequence = Some(media_playlist.media_sequence + (end_index as u64))
+            let first_media_sequence = lowest_media_sequence.max(media_playlist.media_sequence);
+            let last_media_sequence = first_media_sequence + max_segments.saturating_sub(1);
+            highest_media_sequence = Some(
+                highest_media_sequence.map_or(last_media_sequence, |highest| {
+                    highest.min(last_media_sequence)
+                }),
+            );
         }
         remove_segments_from_start(&mut media_playlist, lowest_media_sequence);
-        if let Some(highest_media_sequence) = highest_media_sequence {
+        // Stop once the last segment to record is in the playlist, otherwise keep refreshing
+        if let Some(highest_media_sequence) = highest_media_sequence
+            && highest_media_sequence
+                < media_playlist.media_sequence + (media_playlist.segments.len() as u64)
+        {
             remove_segments_from_end(&mut media_playlist, highest_media_sequence);
         }
+        // Stop refreshing once the scheduled stop time has passed
+        if let Some(stop_at) = options.stop_at
+            && playlist_time >= stop_at
+        {
+            media_playlist.end_list = true;
+        }
+        // Stop refreshing once the maximum size is reached, without downloading new segments
+        if let Some(max_size) = options.max_size
+            && state.size.load(Ordering::Relaxed) >= max_size
+        {
+            match &previous_playlist {
+                Some(previous_playlist) if !previous_playlist.segments.is_empty() => {
+                    let last_media_sequence = previous_playlist.media_sequence
+                        + (previous_playlist.segments.len() as u64)
+                        - 1;
+                    remove_segments_from_end(&mut media_playlist, last_media_sequence);
+                }
+                _ => {
+                    media_playlist.segments.clear();
+                    media_playlist.end_list = true;
+                }
+            }
+        }
+        let next_media_sequence = previous_playlist.as_ref().map_or(0, |previous_playlist| {
+            previous_playlist.media_sequence + (previous_playlist.segments.len() as u64)
+        });
+        // Find the events before rewriting, so they contain the original URIs
+        let new_segment_events = segment_events(
+            &name_in_recording,
+            url,
+            &volatile_query_params,
+            &media_playlist,
+            next_media_sequence,
+            &mut current_key,
+        );
         rewriter.rewrite_media_playlist(&mut media_playlist)?;
         write_media_playlist(&dest.join(&file_name), &media_playlist).await?;
+        let new_segments_duration = (media_playlist.media_sequence..)
+            .zip(&media_playlist.segments)
+            .filter(|(media_sequence, _)| *media_sequence >= next_media_sequence)
+            .map(|(_, segment)| segment.duration)
+            .sum::<f32>();
+        info!(
+            playlist = name_in_recording,
+            media_sequence = media_playlist.media_sequence,
+            segments = media_playlist.segments.len(),
+            ended = media_playlist.end_list,
+            "Playlist: {url}"
+        );
+        state.progress.update(&name_in_recording, |progress| {
+            progress.media_sequence = media_playlist.media_sequence;
+            progress.playlists += 1;
+            // The new segments are not yet downloaded, everything before them is
+            progress.lag = Duration::from_secs_f32(new_segments_duration);
+        });
+        state.events.emit(RecordEvent::PlaylistFetched {
+            playlist: name_in_recording.clone(),
+            path: file_name.clone(),
+            media_sequence: media_playlist.media_sequence,
+            segments: media_playlist.segments.len(),
+            ended: media_playlist.end_list,
+        });
+        for event in new_segment_events {
+            state.events.emit(event);
+        }
+        // Remove snapshots and files that fell out of the rolling window (if given)
+        let expired = rolling_window
+            .as_mut()
+            .map(|rolling_window| {
+                let files = downloaded_file_names(&media_playlist);
+                rolling_window.push(playlist_time, file_name.clone(), files)
+            })
+            .unwrap_or_default();
         // Update recording
-        recording
-            .lock()
-            .await
-            .add_and_save(playlist_time, &name_in_recording, file_name.to_string())
-            .await?;
+        {
+            let mut recording = state.recording.lock().await;
+            for (time, path) in &expired.snapshots {
+                recording.remove(&name_in_recording, time);
+                recording.remove_file(path);
+            }
+            for path in &expired.files {
+                recording.remove_file(&path_in_recording(dir, path));
+            }
+            recording.add_file(file_name.clone(), file_record(&headers));
+            recording
+                .add_and_save(playlist_time, &name_in_recording, file_name.to_string())
+                .await?;
+        }
+        for (_, path) in &expired.snapshots {
+            remove_file(&dest.join(path)).await?;
+        }
+        for path in &expired.files {
+            let removed_size = remove_file(&dest_dir.join(path)).await?;
+            state
+                .size
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |size| {
+                    Some(size.saturating_sub(removed_size))
+                })
+                .unwrap();
+        }
         // Download segments
-        download_segments(
+        let download_start = Instant::now();
+        let (downloaded_files, started_segments) = download_segments(
             client,
             &media_playlist.segments,
             &dest_dir,
-            MAX_CONCURRENT_DOWNLOADS,
+            options
+                .concurrent_downloads
+                .unwrap_or(DEFAULT_CONCURRENT_DOWNLOADS)
+                .get(),
+            &state.progress,
+            &state.events,
+            &state.size,
+            options.max_size,
+            &name_in_recording,
             token.clone(),
         )
-        .await?;
+        .await
+        .inspect_err(|e| {
+            if !matches!(e, RecordError::Cancelled) {
+                state.events.emit(RecordEvent::DownloadError {
+                    playlist: name_in_recording.clone(),
+                    message: e.to_string(),
+                });
+            }
+        })?;
+        let downloaded_size: u64 = downloaded_files.iter().map(|file| file.size).sum();
+        if started_segments < media_playlist.segments.len() {
+            // The maximum size was reached while downloading, so end with the last downloaded segment
+            media_playlist.segments.truncate(started_segments);
+            media_playlist.end_list = true;
+            write_media_playlist(&dest.join(&file_name), &media_playlist).await?;
+        }
+        if !downloaded_files.is_empty() {
+            let mut recording = state.recording.lock().await;
+            for file in &downloaded_files {
+                recording.add_file(
+                    path_in_recording(dir, &file.file_name),
+                    file.record(playlist_time),
+                );
+            }
+            recording.save().await?;
+        }
+        let download_time = download_start.elapsed();
+        state.progress.update(&name_in_recording, |progress| {
+            if downloaded_size > 0 {
+                progress.throughput = downloaded_size as f64 / download_time.as_secs_f64();
+            }
+            if media_playlist.end_list {
+                // Nothing left to catch up on
+                progress.lag = Duration::ZERO;
+            }
+            progress.ended = media_playlist.end_list;
+        });
         // Refresh playlist
         if media_playlist.end_list {
+            state.events.emit(RecordEvent::PlaylistEnded {
+                playlist: name_in_recording.clone(),
+            });
             break;
         }
-        let next_refresh_time = now + Duration::from_secs(media_playlist.target_duration);
+        let mut next_refresh_time = load_start + refresh_delay;
+        if let Some(stop_at) = options.stop_at {
+            // Refresh one last time at the scheduled stop time
+            next_refresh_time = next_refresh_time.min(instant_at(stop_at));
+        }
         token
             .run_until_cancelled(sleep_until(next_refresh_time.into()))
             .await
@@ -346,13 +862,96 @@ async fn record_media_playlist(
     Ok(())
 }
 
-async fn download_playlist(client: &Client, url: &Url) -> Result<String, RecordError> {
-    client
-        .get(url.clone())
-        .send()
-        .and_then(Response::text)
+/// The names of the segments, keys and maps that are downloaded for a rewritten media playlist.
+fn downloaded_file_names(playlist: &MediaPlaylist) -> Vec<String> {
+    let has_tag = |segment: &MediaSegment, tag: &str| {
+        segment
+            .unknown_tags
+            .iter()
+            .any(|ext_tag| ext_tag.tag == tag)
+    };
+    let mut files = Vec::new();
+    for segment in &playlist.segments {
+        files.push(segment.uri.clone());
+        if let Some(uri) = segment.key.as_ref().and_then(|key| key.uri.as_ref())
+            && has_tag(segment, ORIGINAL_KEY_URI)
+        {
+            files.push(uri.clone());
+        }
+        if let Some(map) = &segment.map
+            && has_tag(segment, ORIGINAL_MAP_URI)
+        {
+            files.push(map.uri.clone());
+        }
+    }
+    files
+}
+
+/// The time to wait before reloading a live media playlist, measured from when it started loading.
+///
+/// As recommended by the HLS specification, this is the duration of the last segment
+/// if the playlist has changed, or half the target duration if it hasn't.
+/// (The target duration is rounded, so using it would make the recording lag behind.)
+fn refresh_delay(
+    playlist: &MediaPlaylist,
+    changed: bool,
+    poll_interval: Option<Duration>,
+) -> Duration {
+    if let Some(poll_interval) = poll_interval {
+        return poll_interval;
+    }
+    let target_duration = Duration::from_secs(playlist.target_duration);
+    if !changed {
+        return target_duration / 2;
+    }
+    playlist
+        .segments
+        .last()
+        .and_then(|segment| Duration::try_from_secs_f32(segment.duration).ok())
+        .filter(|duration| !duration.is_zero())
+        .unwrap_or(target_duration)
+}
+
+/// Convert a wall-clock time to an [`Instant`], for use with [`sleep_until`].
+fn instant_at(time: DateTime<Utc>) -> Instant {
+    let delay = (time - Utc::now()).to_std().unwrap_or_default();
+    Instant::now() + delay
+}
+
+/// Download a playlist, and return its contents along with the response headers.
+async fn download_playlist(
+    client: &HttpClient,
+    url: &Url,
+) -> Result<(String, HeaderMap), RecordError> {
+    let _permit = client.acquire(url).await;
+    let response = client.get(url, HeaderMap::new()).await?;
+    if !response.status().is_success() {
+        return Err(RecordError::Http {
+            url: url.clone(),
+            status: response.status(),
+        });
+    }
+    let headers = response.headers().clone();
+    let raw_playlist = response
+        .text()
         .await
-        .map_err(|e| RecordError::Io(io::Error::other(e)))
+        .map_err(|e| RecordError::Io(io::Error::other(e)))?;
+    Ok((raw_playlist, headers))
+}
+
+async fn download_media_playlist(
+    client: &HttpClient,
+    url: &Url,
+) -> Result<(MediaPlaylist, HeaderMap), RecordError> {
+    let (raw_playlist, headers) = download_playlist(client, url).await?;
+    let raw_playlist = raw_playlist.strip_bom();
+    let media_playlist = parse_media_playlist_res(raw_playlist.as_bytes()).map_err(|e| {
+        RecordError::Parse(anyhow!(
+            "Error while parsing media playlist: {}",
+            e.map_input(|i| String::from_utf8_lossy(i))
+        ))
+    })?;
+    Ok((media_playlist, headers))
 }
 
 async fn write_master_playlist(
@@ -377,70 +976,113 @@ async fn write_media_playlist(
     Ok(())
 }
 
-fn find_segment_index_by_offset(segments: &[MediaSegment], offset: f32) -> Option<usize> {
-    #[inline]
-    fn find<'a>(
-        iter: impl Iterator<Item = (usize, &'a MediaSegment)>,
-        target_time: f32,
-    ) -> Option<usize> {
-        let mut start_time = 0.0;
-        for (index, segment) in iter {
-            let end_time = start_time + segment.duration;
-            if (start_time..end_time).contains(&target_time) {
-                return Some(index);
-            }
-            start_time = end_time;
-        }
-        None
-    }
-
-    if offset >= 0.0 {
-        find(segments.iter().enumerate(), offset)
-    } else {
-        find(segments.iter().enumerate().rev(), -offset)
-    }
-}
-
+/// Download the given segments, along with their keys and maps.
+///
+/// Once the recording reaches `max_size`, no more segments are started.
+/// Returns the downloaded files, and the number of segments that were started.
+#[allow(clippy::too_many_arguments)]
 async fn download_segments(
-    client: &Client,
+    client: &HttpClient,
     media_segments: &[MediaSegment],
     dir: &Path,
     max_concurrent_downloads: usize,
+    progress: &RecordProgress,
+    events: &RecordEvents,
+    total_size: &AtomicU64,
+    max_size: Option<u64>,
+    playlist_name: &str,
     token: CancellationToken,
-) -> Result<(), RecordError> {
+) -> Result<(Vec<DownloadedFile>, usize), RecordError> {
+    let started_segments = AtomicUsize::new(0);
     let segment_tasks = media_segments
         .iter()
-        .flat_map(|segment| make_segment_download_tasks(client, dir, segment, token.clone()));
-    iter(segment_tasks)
+        .take_while(|_| {
+            // Checked lazily, right before each segment starts downloading
+            max_size.is_none_or(|max_size| total_size.load(Ordering::Relaxed) < max_size)
+        })
+        .inspect(|_| {
+            started_segments.fetch_add(1, Ordering::Relaxed);
+        })
+        .flat_map(|segment| {
+            make_segment_download_tasks(
+                client,
+                dir,
+                segment,
+                progress,
+                events,
+                total_size,
+                playlist_name,
+                token.clone(),
+            )
+        });
+    let files = iter(segment_tasks)
         .boxed() // https://github.com/rust-lang/rust/issues/104382
         .buffered(max_concurrent_downloads)
-        .try_collect::<()>() // drop individual results
-        .await
+        .try_filter_map(|file| async move { Ok(file) })
+        .try_collect()
+        .await?;
+    Ok((files, started_segments.into_inner()))
 }
 
+#[allow(clippy::too_many_arguments)]
 fn make_segment_download_tasks<'a>(
-    client: &'a Client,
+    client: &'a HttpClient,
     dir: &'a Path,
     segment: &'a MediaSegment,
+    progress: &'a RecordProgress,
+    events: &'a RecordEvents,
+    total_size: &'a AtomicU64,
+    playlist_name: &'a str,
     token: CancellationToken,
-) -> Vec<BoxFuture<'a, Result<(), RecordError>>> {
+) -> Vec<BoxFuture<'a, Result<Option<DownloadedFile>, RecordError>>> {
     let mut tasks = Vec::with_capacity(3);
-    tasks.push(download_segment(client, segment, dir, token.clone()).boxed());
+    tasks.push(
+        download_segment(client, segment, dir, token.clone())
+            .inspect_ok(move |file| {
+                let size = file.as_ref().map_or(0, |file| file.size);
+                total_size.fetch_add(size, Ordering::Relaxed);
+                progress.segment_downloaded(playlist_name, size);
+                if let Some(file) = file {
+                    events.emit(RecordEvent::SegmentDownloaded {
+                        playlist: playlist_name.to_string(),
+                        file_name: file.file_name.clone(),
+                        size,
+                    });
+                }
+            })
+            .boxed(),
+    );
     if let Some(key) = segment.key.as_ref() {
-        tasks.push(download_key(client, key, segment, dir, token.clone()).boxed());
+        tasks.push(
+            download_key(client, key, segment, dir, token.clone())
+                .inspect_ok(move |file| {
+                    let size = file.as_ref().map_or(0, |file| file.size);
+                    total_size.fetch_add(size, Ordering::Relaxed);
+                    progress.file_downloaded(playlist_name, size)
+                })
+                .boxed(),
+        );
     }
     if let Some(map) = segment.map.as_ref() {
-        tasks.push(download_map(client, map, segment, dir, token).boxed());
+        tasks.push(
+            download_map(client, map, segment, dir, token)
+                .inspect_ok(move |file| {
+                    let size = file.as_ref().map_or(0, |file| file.size);
+                    total_size.fetch_add(size, Ordering::Relaxed);
+                    progress.file_downloaded(playlist_name, size)
+                })
+                .boxed(),
+        );
     }
     tasks
 }
 
 async fn download_segment(
-    client: &Client,
+    client: &HttpClient,
     media_segment: &MediaSegment,
     dir: &Path,
     token: CancellationToken,
-) -> Result<(), RecordError> {
+) -> Result<Option<DownloadedFile>, RecordError> {
     let segment_url = media_segment
         .unknown_tags
         .iter()
@@ -471,18 +1113,18 @@ async fn download_segment(
 }
 
 async fn download_key(
-    client: &Client,
+    client: &HttpClient,
     key: &Key,
     media_segment: &MediaSegment,
     dir: &Path,
     token: CancellationToken,
-) -> Result<(), RecordError> {
+) -> Result<Option<DownloadedFile>, RecordError> {
     let Some(original_key_tag) = &media_segment
         .unknown_tags
         .iter()
         .find(|ext_tag| ext_tag.tag == ORIGINAL_KEY_URI)
     else {
-        return Ok(());
+        return Ok(None);
     };
     let key_uri = original_key_tag.rest.as_ref().unwrap();
     let key_file = key.uri.as_ref().unwrap();
@@ -498,18 +1140,18 @@ async fn download_key(
 }
 
 async fn download_map(
-    client: &Client,
+    client: &HttpClient,
     map: &Map,
     media_segment: &MediaSegment,
     dir: &Path,
     token: CancellationToken,
-) -> Result<(), RecordError> {
+) -> Result<Option<DownloadedFile>, RecordError> {
     let Some(original_map_tag) = &media_segment
         .unknown_tags
         .iter()
         .find(|ext_tag| ext_tag.tag == ORIGINAL_MAP_URI)
     else {
-        return Ok(());
+        return Ok(None);
     };
     let map_uri = original_map_tag.rest.as_ref().unwrap();
     let map_byte_range = map
@@ -533,46 +1175,149 @@ async fn download_map(
 }
 
 async fn download_file(
-    client: &Client,
+    client: &HttpClient,
     url: &str,
     byte_range: Option<ByteRange>,
     file_name: &str,
     dir: &Path,
     token: CancellationToken,
-) -> Result<(), RecordError> {
+) -> Result<Option<DownloadedFile>, RecordError> {
     let absolute_path = dir.join(file_name);
-    let mut file = match fs::OpenOptions::new()
+    // Creating the file claims it, so it's only downloaded once
+    let file = match fs::OpenOptions::new()
         .write(true)
         .create_new(true)
         .open(&absolute_path)
         .await
     {
         Ok(file) => file,
-        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(()),
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => return Ok(None),
         Err(err) => return Err(err.into()),
     };
+    let mut partial_file = PartialFile {
+        path: absolute_path,
+        completed: false,
+    };
+    // Close the file before the partial file is removed
+    let mut file = file;
     let range_header = byte_range.map(|byte_range| {
         let start = byte_range.offset;
         let end = start + byte_range.length - 1; // end byte for a range request is inclusive!
         format!("bytes={start}-{end}")
     });
-    println!(
-        "Download: {url} {}",
-        range_header.as_ref().unwrap_or(&String::new())
-    );
-    let mut request = client.get(url);
+    let url = Url::parse(url).map_err(|e| RecordError::Parse(anyhow!("Invalid URL {url}: {e}")))?;
+    let _permit = token
+        .run_until_cancelled(client.acquire(&url))
+        .await
+        .ok_or(RecordError::Cancelled)?;
+    debug!(%url, range = range_header.as_deref(), "Download");
+    let mut headers = HeaderMap::new();
     if let Some(range_header) = range_header {
-        request = request.header(reqwest::header::RANGE, range_header);
+        headers.insert(RANGE, HeaderValue::from_str(&range_header).unwrap());
     }
+    let request_start = Instant::now();
     let response = token
-        .run_until_cancelled(request.send())
+        .run_until_cancelled(client.get(&url, headers))
         .await
-        .ok_or(RecordError::Cancelled)?
-        .map_err(|e| RecordError::Io(io::Error::other(e)))?;
+        .ok_or(RecordError::Cancelled)??;
+    let latency = request_start.elapsed();
+    if !response.status().is_success() {
+        return Err(RecordError::Http {
+            url,
+            status: response.status(),
+        });
+    }
+    let headers = response.headers().clone();
     let response_stream = response.bytes_stream().map_err(io::Error::other);
     let mut response_stream = StreamReader::new(response_stream);
-    tokio::io::copy_buf(&mut response_stream, &mut file).await?;
-    Ok(())
+    let size = tokio::io::copy_buf(&mut response_stream, &mut file).await?;
+    partial_file.completed = true;
+    Ok(Some(DownloadedFile {
+        file_name: file_name.to_string(),
+        size,
+        headers,
+        latency,
+        downloaded: Utc::now(),
+    }))
+}
+
+/// Removes a file when its download fails or is cancelled,
+/// so it's downloaded again instead of being kept with an error or partial response.
+struct PartialFile {
+    path: PathBuf,
+    completed: bool,
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if !self.completed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// A newly downloaded segment, key or map file.
+struct DownloadedFile {
+    /// The file name, relative to the directory of its media playlist
+    file_name: String,
+    size: u64,
+    headers: HeaderMap,
+    /// The time until the response headers were received
+    latency: Duration,
+    downloaded: DateTime<Utc>,
+}
+
+impl DownloadedFile {
+    /// Create the file record, for a file that first appeared in a playlist at `first_seen`.
+    fn record(&self, first_seen: DateTime<Utc>) -> FileRecord {
+        FileRecord {
+            first_seen: Some(first_seen),
+            downloaded: Some(self.downloaded),
+            size: Some(self.size),
+            latency: Some(self.latency.as_millis() as u64),
+            ..file_record(&self.headers)
+        }
+    }
+}
+
+/// Whether a status code means that a playlist was removed from the server.
+fn is_gone(status: StatusCode) -> bool {
+    matches!(status, StatusCode::NOT_FOUND | StatusCode::GONE)
+}
+
+/// Get the path of a file relative to the recording,
+/// for a file name relative to the directory of its media playlist.
+fn path_in_recording(dir: &str, file_name: &str) -> String {
+    Path::new(dir).join(file_name).to_string_lossy().to_string()
+}
+
+fn file_record(headers: &HeaderMap) -> FileRecord {
+    // Cookies are private to the recording user, and are saved separately with `save_cookies`
+    let headers = headers
+        .iter()
+        .filter(|(name, _)| *name != SET_COOKIE)
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+    FileRecord {
+        headers,
+        ..Default::default()
+    }
+}
+
+/// Remove a file, and return its size.
+async fn remove_file(path: &Path) -> Result<u64, RecordError> {
+    let size = match fs::metadata(path).await {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(err) => return Err(err.into()),
+    };
+    fs::remove_file(path).await?;
+    Ok(size)
+}
+
+fn original_url(variant: &VariantStream) -> Option<Url> {
+    let original_uri = variant.other_attributes.as_ref()?.get(ORIGINAL_URI)?;
+    Some(Url::parse(original_uri.as_str()).unwrap())
 }
 
 fn media_applies_to_variant(media: &AlternativeMedia, variant_stream: &VariantStream) -> bool {
@@ -590,18 +1335,22 @@ fn media_applies_to_variant(media: &AlternativeMedia, variant_stream: &VariantSt
     }
 }
 
+/// The `recording.json` file of a recording, saved after every change.
+///
+/// It is only saved through the lock in [`RecordState`], so saves never overlap.
 struct RecordingFile {
     recording: Recording,
-    file: fs::File,
+    path: PathBuf,
 }
 
 impl RecordingFile {
     async fn new(path: &Path) -> io::Result<Self> {
-        let file = fs::File::create(path).await?;
-        Ok(Self {
+        let mut file = Self {
             recording: Recording::new(),
-            file,
-        })
+            path: path.to_owned(),
+        };
+        file.save().await?;
+        Ok(file)
     }
 
     async fn add_and_save(
@@ -614,19 +1363,44 @@ impl RecordingFile {
         self.save().await
     }
 
+    fn remove(&mut self, playlist_name: &str, time: &DateTime<Utc>) -> Option<String> {
+        self.recording.remove(playlist_name, time)
+    }
+
+    fn metadata_mut(&mut self) -> &mut RecordingMetadata {
+        self.recording.metadata_mut()
+    }
+
+    fn add_file(&mut self, path: String, file: FileRecord) {
+        self.recording.add_file(path, file);
+    }
+
+    fn remove_file(&mut self, path: &str) -> Option<FileRecord> {
+        self.recording.remove_file(path)
+    }
+
     async fn save(&mut self) -> io::Result<()> {
         let recording_json = serde_json::to_string_pretty(&self.recording)?;
-        let recording_bytes = recording_json.as_bytes();
-        self.file.rewind().await?;
-        self.file.write_all(recording_bytes).await?;
-        self.file.set_len(recording_bytes.len() as u64).await?;
-        Ok(())
+        write_atomic(&self.path, recording_json.as_bytes()).await
     }
 }
 
+/// Write a file atomically, by first writing to a temporary file and then renaming it,
+/// so the file is never left partially written.
+async fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let mut temp_file = fs::File::create(&temp_path).await?;
+    temp_file.write_all(contents).await?;
+    temp_file.sync_all().await?;
+    drop(temp_file);
+    fs::rename(&temp_path, path).await
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use reqwest::Client;
 
     /// Test whether all `async fn`s are `Send`,
     /// so they can be scheduled on Tokio's multi-threaded runtime.
@@ -634,14 +1408,26 @@ mod tests {
     fn require_async_fn_to_be_send() {
         let url = Url::parse("https://a.com/").unwrap();
         let path = Path::new("");
-        let client = Client::new();
+        let client = HttpClient::new(Client::new(), None, None, None);
         let token = CancellationToken::new();
 
         fn require_send<T: Send>(_t: T) {}
         require_send(download_playlist(&client, &url));
+        require_send(download_media_playlist(&client, &url));
         require_send(write_master_playlist(path, &MasterPlaylist::default()));
         require_send(write_media_playlist(path, &MediaPlaylist::default()));
-        require_send(download_segments(&client, &[], path, 0, token.clone()));
+        require_send(download_segments(
+            &client,
+            &[],
+            path,
+            0,
+            &RecordProgress::new(),
+            &RecordEvents::new(),
+            &AtomicU64::new(0),
+            None,
+            "",
+            token.clone(),
+        ));
         require_send(download_segment(
             &client,
             &MediaSegment::empty(),
@@ -663,5 +1449,74 @@ mod tests {
             token.clone(),
         ));
         require_send(download_file(&client, "", None, "", path, token.clone()));
+        require_send(remove_file(path));
+    }
+
+    #[test]
+    fn downloaded_files_of_playlist() {
+        let url = Url::parse("https://a.com/live/index.m3u8").unwrap();
+        let mut playlist = parse_media_playlist_res(
+            b"#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-MAP:URI=\"init.mp4\"
+#EXT-X-KEY:METHOD=AES-128,URI=\"key1.key\"
+#EXTINF:4.0,
+1.m4s
+#EXT-X-KEY:METHOD=SAMPLE-AES,URI=\"skd://key2\"
+#EXTINF:4.0,
+2.m4s
+",
+        )
+        .unwrap();
+        let mut rewriter = Rewriter::new(&url, "live".as_ref(), true);
+        rewriter.rewrite_media_playlist(&mut playlist).unwrap();
+        // Keys that are not downloaded are not included
+        assert_eq!(
+            downloaded_file_names(&playlist),
+            ["1.m4s", "key1.key", "init.mp4", "2.m4s"]
+        );
+    }
+
+    #[test]
+    fn file_record_without_cookies() {
+        let mut headers = HeaderMap::new();
+        headers.insert("content-type", HeaderValue::from_static("video/mp2t"));
+        headers.insert(SET_COOKIE, HeaderValue::from_static("session=abc"));
+        assert_eq!(
+            file_record(&headers).headers,
+            [("content-type".to_string(), "video/mp2t".to_string())]
+        );
+    }
+
+    #[test]
+    fn refresh_delays() {
+        let playlist = MediaPlaylist {
+            target_duration: 6,
+            segments: vec![MediaSegment {
+                duration: 5.005,
+                ..MediaSegment::empty()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            refresh_delay(&playlist, true, None),
+            Duration::from_secs_f32(5.005)
+        );
+        assert_eq!(
+            refresh_delay(&playlist, false, None),
+            Duration::from_secs(3)
+        );
+        assert_eq!(
+            refresh_delay(&playlist, false, Some(Duration::from_millis(500))),
+            Duration::from_millis(500)
+        );
+        let empty_playlist = MediaPlaylist {
+            target_duration: 6,
+            ..Default::default()
+        };
+        assert_eq!(
+            refresh_delay(&empty_playlist, true, None),
+            Duration::from_secs(6)
+        );
     }
 }
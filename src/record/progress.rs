@@ -0,0 +1,116 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use tracing::info;
+
+/// The live progress of a recording, for each recorded media playlist.
+///
+/// This is cheap to clone, all clones share the same progress.
+#[derive(Debug, Default, Clone)]
+pub struct RecordProgress {
+    playlists: Arc<Mutex<IndexMap<String, PlaylistProgress>>>,
+}
+
+/// The progress of a single media playlist in a recording.
+#[derive(Debug, Default, Clone)]
+pub struct PlaylistProgress {
+    /// The media sequence number of the latest playlist.
+    pub media_sequence: u64,
+    /// The number of playlists fetched so far.
+    pub playlists: u64,
+    /// The number of segments downloaded so far.
+    pub segments: u64,
+    /// The number of bytes downloaded so far, including keys and maps.
+    pub bytes: u64,
+    /// The download throughput of the latest batch of segments, in bytes per second.
+    pub throughput: f64,
+    /// How far the recording is behind the end of the live playlist:
+    /// the duration of the segments after the last downloaded segment,
+    /// when the latest playlist was fetched.
+    pub lag: Duration,
+    /// Whether this playlist has ended.
+    pub ended: bool,
+}
+
+impl RecordProgress {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the current progress of every media playlist, keyed by playlist name.
+    pub fn playlists(&self) -> IndexMap<String, PlaylistProgress> {
+        self.playlists.lock().unwrap().clone()
+    }
+
+    /// Get the combined progress of all media playlists.
+    pub fn total(&self) -> PlaylistProgress {
+        let playlists = self.playlists.lock().unwrap();
+        let mut total = PlaylistProgress {
+            ended: !playlists.is_empty(),
+            ..PlaylistProgress::default()
+        };
+        for playlist in playlists.values() {
+            total.playlists += playlist.playlists;
+            total.segments += playlist.segments;
+            total.bytes += playlist.bytes;
+            total.throughput += playlist.throughput;
+            total.lag = total.lag.max(playlist.lag);
+            total.ended &= playlist.ended;
+        }
+        total
+    }
+
+    pub(crate) fn update(&self, playlist_name: &str, f: impl FnOnce(&mut PlaylistProgress)) {
+        let mut playlists = self.playlists.lock().unwrap();
+        if let Some(playlist) = playlists.get_mut(playlist_name) {
+            f(playlist)
+        } else {
+            f(playlists.entry(playlist_name.to_string()).or_default())
+        }
+    }
+
+    pub(crate) fn segment_downloaded(&self, playlist_name: &str, size: u64) {
+        if size == 0 {
+            // Already downloaded
+            return;
+        }
+        self.update(playlist_name, |playlist| {
+            playlist.segments += 1;
+            playlist.bytes += size;
+        });
+    }
+
+    pub(crate) fn file_downloaded(&self, playlist_name: &str, size: u64) {
+        if size == 0 {
+            // Already downloaded
+            return;
+        }
+        self.update(playlist_name, |playlist| playlist.bytes += size);
+    }
+
+    /// Log a summary of the recording.
+    pub(crate) fn log_summary(&self) {
+        for (name, playlist) in self.playlists() {
+            info!(
+                playlist = name,
+                playlists = playlist.playlists,
+                segments = playlist.segments,
+                bytes = playlist.bytes,
+                ended = playlist.ended,
+                "Recorded {} segments ({} bytes) of {name}",
+                playlist.segments,
+                playlist.bytes,
+            );
+        }
+        let total = self.total();
+        info!(
+            playlists = total.playlists,
+            segments = total.segments,
+            bytes = total.bytes,
+            "Recorded {} segments ({} bytes) in total",
+            total.segments,
+            total.bytes,
+        );
+    }
+}
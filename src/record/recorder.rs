@@ -0,0 +1,134 @@
+use std::panic;
+use std::path::PathBuf;
+
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+
+use crate::record::{
+    RecordError, RecordEvent, RecordEvents, RecordOptions, RecordProgress, record_with_events,
+};
+
+/// Builder for a recording that runs in the background.
+///
+/// ```no_run
+/// # use streamrr::record::{RecordEvent, RecordOptions, Recorder};
+/// # async fn example(url: url::Url) -> Result<(), streamrr::record::RecordError> {
+/// let recorder = Recorder::new(url, "recording").options(RecordOptions::default());
+/// let mut events = recorder.subscribe();
+/// let handle = recorder.start();
+/// while let Ok(event) = events.recv().await {
+///     println!("{event:?}");
+///     if let RecordEvent::Stopped { .. } = event {
+///         break;
+///     }
+/// }
+/// handle.wait().await
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Recorder {
+    url: Url,
+    dest: PathBuf,
+    options: RecordOptions,
+    progress: RecordProgress,
+    events: RecordEvents,
+}
+
+impl Recorder {
+    /// Record the stream at `url` to the directory `dest`.
+    pub fn new(url: Url, dest: impl Into<PathBuf>) -> Self {
+        Self {
+            url,
+            dest: dest.into(),
+            options: RecordOptions::default(),
+            progress: RecordProgress::new(),
+            events: RecordEvents::new(),
+        }
+    }
+
+    /// How to record the stream, e.g. which variants to record and when to stop.
+    pub fn options(mut self, options: RecordOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Report the progress of the recording to `progress`.
+    pub fn progress(mut self, progress: RecordProgress) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    /// Receive all events of the recording.
+    ///
+    /// Subscribe before calling [`start`](Self::start) to not miss any events.
+    pub fn subscribe(&self) -> broadcast::Receiver<RecordEvent> {
+        self.events.subscribe()
+    }
+
+    /// Start recording in a background task.
+    ///
+    /// This must be called from within a Tokio runtime.
+    pub fn start(self) -> RecorderHandle {
+        let token = CancellationToken::new();
+        let task = tokio::spawn({
+            let token = token.clone();
+            let events = self.events.clone();
+            let progress = self.progress.clone();
+            async move {
+                record_with_events(&self.url, &self.dest, self.options, progress, events, token)
+                    .await
+            }
+        });
+        RecorderHandle {
+            token,
+            task,
+            progress: self.progress,
+            events: self.events,
+        }
+    }
+}
+
+/// A handle to a running recording, returned by [`Recorder::start`].
+#[derive(Debug)]
+pub struct RecorderHandle {
+    token: CancellationToken,
+    task: JoinHandle<Result<(), RecordError>>,
+    progress: RecordProgress,
+    events: RecordEvents,
+}
+
+impl RecorderHandle {
+    /// Receive all further events of the recording.
+    pub fn subscribe(&self) -> broadcast::Receiver<RecordEvent> {
+        self.events.subscribe()
+    }
+
+    /// The progress of the recording, as reported to [`Recorder::progress`].
+    pub fn progress(&self) -> &RecordProgress {
+        &self.progress
+    }
+
+    /// Stop recording.
+    ///
+    /// The recording is finalized in the background, use [`wait`](Self::wait) to wait for it.
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+
+    /// Whether the recording has stopped.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Wait until the recording stops.
+    ///
+    /// Returns [`RecordError::Cancelled`] if it was stopped with [`stop`](Self::stop).
+    pub async fn wait(self) -> Result<(), RecordError> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(e) => panic::resume_unwind(e.into_panic()),
+        }
+    }
+}
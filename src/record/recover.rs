@@ -0,0 +1,120 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::record::rewrite::SNAPSHOT_TIME_FORMAT;
+use crate::record::{RecordError, write_atomic};
+use crate::shared::Recording;
+
+/// Rebuild the `recording.json` of a recording, by scanning the playlist files on disk.
+///
+/// Snapshots of live playlists are found by the timestamp in their file name,
+/// other playlists use the modification time of their file.
+/// Any existing `recording.json` is kept as `recording.json.bak`.
+pub async fn recover(dest: &Path) -> Result<Recording, RecordError> {
+    let mut snapshots = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+    while let Some(dir) = dirs.pop() {
+        let mut entries = fs::read_dir(dest.join(&dir)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = dir.join(entry.file_name());
+            let file_type = entry.file_type().await?;
+            if file_type.is_dir() {
+                dirs.push(path);
+                continue;
+            }
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            if !file_name.ends_with(".m3u8") {
+                continue;
+            }
+            let (playlist_name, time) = match parse_snapshot_name(&file_name) {
+                Some((name, time)) => (dir.join(name), time),
+                None => {
+                    let modified = entry.metadata().await?.modified()?;
+                    (path.clone(), DateTime::<Utc>::from(modified))
+                }
+            };
+            snapshots.push((
+                time,
+                playlist_name.to_string_lossy().to_string(),
+                path.to_string_lossy().to_string(),
+            ));
+        }
+    }
+    if snapshots.is_empty() {
+        return Err(RecordError::Parse(anyhow::anyhow!(
+            "No playlists found in {}",
+            dest.display()
+        )));
+    }
+    // Add the playlists in order, so the master playlist comes first
+    snapshots.sort();
+    let mut recording = Recording::new();
+    for (time, playlist_name, path) in snapshots {
+        recording.add(time, &playlist_name, path);
+    }
+    let recording_path = dest.join("recording.json");
+    if fs::try_exists(&recording_path).await? {
+        let backup_path = dest.join("recording.json.bak");
+        warn!("Keeping old recording as {}", backup_path.display());
+        fs::rename(&recording_path, &backup_path).await?;
+    }
+    let recording_json = serde_json::to_string_pretty(&recording).map_err(io::Error::from)?;
+    write_atomic(&recording_path, recording_json.as_bytes()).await?;
+    info!(
+        "Recovered {} playlists in {}",
+        recording.playlist_names().count(),
+        recording_path.display()
+    );
+    Ok(recording)
+}
+
+/// Parse the file name of a snapshot of a live playlist, e.g. `index-20260701T120000123.m3u8`,
+/// into the playlist name and the time of the snapshot.
+///
+/// Also accepts the timestamps without milliseconds of older recordings.
+fn parse_snapshot_name(file_name: &str) -> Option<(String, DateTime<Utc>)> {
+    let stem = file_name.strip_suffix(".m3u8")?;
+    let (name, timestamp) = stem.rsplit_once('-')?;
+    let time = NaiveDateTime::parse_from_str(timestamp, SNAPSHOT_TIME_FORMAT)
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y%m%dT%H%M%S"))
+        .ok()?;
+    Some((format!("{name}.m3u8"), time.and_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_snapshot_names() {
+        assert_eq!(
+            parse_snapshot_name("index-20260701T120005.m3u8"),
+            Some((
+                "index.m3u8".to_string(),
+                Utc.with_ymd_and_hms(2026, 7, 1, 12, 0, 5).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_snapshot_name("my-stream-20260701T120005.m3u8"),
+            Some((
+                "my-stream.m3u8".to_string(),
+                Utc.with_ymd_and_hms(2026, 7, 1, 12, 0, 5).unwrap()
+            ))
+        );
+        assert_eq!(
+            parse_snapshot_name("index-20260701T120005250.m3u8"),
+            Some((
+                "index.m3u8".to_string(),
+                Utc.with_ymd_and_hms(2026, 7, 1, 12, 0, 5).unwrap()
+                    + chrono::TimeDelta::milliseconds(250)
+            ))
+        );
+        assert_eq!(parse_snapshot_name("index.m3u8"), None);
+        assert_eq!(parse_snapshot_name("my-stream.m3u8"), None);
+    }
+}
@@ -1,6 +1,6 @@
 use crate::shared::{ByteRange, hex, url_file_extension, url_file_name};
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, FixedOffset, TimeDelta, Utc};
 use m3u8_rs::*;
 use sha1::{Digest, Sha1};
 use std::collections::HashMap;
@@ -16,6 +16,9 @@ pub const ORIGINAL_PRELOAD_HINT: &str = "X-ORIGINAL-PRELOAD-HINT";
 pub const ORIGINAL_RENDITION_REPORT: &str = "X-ORIGINAL-RENDITION-REPORT";
 
 const DEFAULT_FILE_EXT: &str = "ts";
+/// The format of the timestamp in the file name of a live playlist snapshot,
+/// with milliseconds so snapshots in the same second don't overwrite each other.
+pub(crate) const SNAPSHOT_TIME_FORMAT: &str = "%Y%m%dT%H%M%S%3f";
 
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
@@ -29,6 +32,7 @@ pub struct Rewriter<'a> {
     playlist_url: &'a Url,
     dest: &'a Path,
     keep_names: bool,
+    volatile_query_params: Vec<String>,
     last_segment_ext: String,
 }
 
@@ -38,10 +42,17 @@ impl<'a> Rewriter<'a> {
             playlist_url,
             dest,
             keep_names,
+            volatile_query_params: Vec::new(),
             last_segment_ext: DEFAULT_FILE_EXT.to_string(),
         }
     }
 
+    /// Ignore the given query parameters when generating file names from URLs,
+    /// e.g. because they contain a short-lived authentication token.
+    pub fn set_volatile_query_params(&mut self, names: Vec<String>) {
+        self.volatile_query_params = names;
+    }
+
     pub fn build_playlist_name<'b>(&self, url: &'b Url) -> &'b str {
         if self.keep_names
             && let Some(file_name) = url_file_name(url)
@@ -69,7 +80,7 @@ impl<'a> Rewriter<'a> {
         let file_name_with_timestamp = format!(
             "{}-{}.m3u8",
             file_name.strip_suffix(".m3u8").unwrap_or(file_name),
-            timestamp.format("%Y%m%dT%H%M%S")
+            timestamp.format(SNAPSHOT_TIME_FORMAT)
         );
         self.dest
             .join(file_name_with_timestamp)
@@ -203,7 +214,7 @@ impl<'a> Rewriter<'a> {
             // Use a hash of the key URL as filename.
             // Don't use the media sequence number, since it's likely that this key will appear
             // on a different segment in a future media playlist.
-            let key_url_hash = Sha1::digest(key_url.as_str().as_bytes());
+            let key_url_hash = Sha1::digest(self.stable_url(&key_url).as_str().as_bytes());
             format!("key-{}.bin", hex(key_url_hash))
         };
         *key_uri = key_name;
@@ -239,7 +250,7 @@ impl<'a> Rewriter<'a> {
             // Use a hash of the key URL as filename.
             // Don't use the media sequence number, since it's likely that this key will appear
             // on a different segment in a future media playlist.
-            let map_url_hash = Sha1::digest(map_url.as_str().as_bytes());
+            let map_url_hash = Sha1::digest(self.stable_url(&map_url).as_str().as_bytes());
             let file_ext = url_file_extension(&map_url).unwrap_or(DEFAULT_FILE_EXT);
             format!("init-{}.{}", hex(map_url_hash), file_ext)
         };
@@ -264,6 +275,11 @@ impl<'a> Rewriter<'a> {
         }
     }
 
+    /// Remove the volatile query parameters from a URL.
+    fn stable_url(&self, url: &Url) -> Url {
+        stable_url(url, &self.volatile_query_params)
+    }
+
     fn get_or_update_file_ext(&mut self, url: &Url) -> &str {
         if let Some(ext) = url_file_extension(url)
             && ext != self.last_segment_ext
@@ -279,10 +295,14 @@ pub fn remove_segments_from_start(media_playlist: &mut MediaPlaylist, lowest_med
     if media_playlist.media_sequence >= lowest_media_sequence {
         return;
     }
-    let remove_count = lowest_media_sequence - media_playlist.media_sequence;
-    // Remove segments, but keep track of the last key and map
+    let remove_count = (lowest_media_sequence - media_playlist.media_sequence)
+        .min(media_playlist.segments.len() as u64);
+    // Remove segments, but keep track of the last key and map,
+    // the number of discontinuities and the program date time
     let mut last_key = None;
     let mut last_map = None;
+    let mut discontinuity_count = 0;
+    let mut next_program_date_time: Option<DateTime<FixedOffset>> = None;
     for mut removed_segment in media_playlist.segments.drain(0..(remove_count as usize)) {
         if let Some(key) = removed_segment.key.take() {
             last_key = Some(key);
@@ -290,17 +310,28 @@ pub fn remove_segments_from_start(media_playlist: &mut MediaPlaylist, lowest_med
         if let Some(map) = removed_segment.map.take() {
             last_map = Some(map);
         }
+        if removed_segment.discontinuity {
+            discontinuity_count += 1;
+        }
+        next_program_date_time = removed_segment
+            .program_date_time
+            .or(next_program_date_time)
+            .map(|time| time + TimeDelta::milliseconds((removed_segment.duration * 1000.0) as i64));
     }
     media_playlist.media_sequence = lowest_media_sequence;
-    // Put the last key and map onto the new first segment
+    media_playlist.discontinuity_sequence += discontinuity_count;
+    // Put the last key, map and program date time onto the new first segment
     if let Some(first_segment) = media_playlist.segments.first_mut() {
         first_segment.key = first_segment.key.take().or(last_key);
         first_segment.map = first_segment.map.take().or(last_map);
+        first_segment.program_date_time =
+            first_segment.program_date_time.or(next_program_date_time);
     }
 }
 
 pub fn remove_segments_from_end(media_playlist: &mut MediaPlaylist, highest_media_sequence: u64) {
-    let remove_start = (highest_media_sequence - media_playlist.media_sequence + 1) as usize;
+    let remove_start =
+        (highest_media_sequence + 1).saturating_sub(media_playlist.media_sequence) as usize;
     if remove_start < media_playlist.segments.len() {
         media_playlist.segments.drain(remove_start..);
     }
@@ -318,6 +349,25 @@ fn remove_unsupported_tags(ext_tags: &mut Vec<ExtTag>) {
     });
 }
 
+/// Remove the given query parameters from a URL.
+pub(crate) fn stable_url(url: &Url, volatile_query_params: &[String]) -> Url {
+    let mut url = url.clone();
+    if volatile_query_params.is_empty() || url.query().is_none() {
+        return url;
+    }
+    let query: Vec<(String, String)> = url
+        .query_pairs()
+        .filter(|(name, _)| !volatile_query_params.iter().any(|param| param == name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    if query.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(query);
+    }
+    url
+}
+
 /// Strip inserted tags with original playlist information from a media playlist
 pub fn strip_media_playlist(media_playlist: &mut MediaPlaylist) {
     media_playlist.unknown_tags.retain_mut(|ext_tag| {
@@ -0,0 +1,142 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Keeps track of the snapshots of a media playlist within a rolling time window,
+/// so snapshots and files that fall out of the window can be deleted.
+#[derive(Debug)]
+pub(crate) struct RollingWindow {
+    duration: TimeDelta,
+    snapshots: VecDeque<Snapshot>,
+    /// The number of snapshots referencing each file
+    file_refs: HashMap<String, usize>,
+}
+
+#[derive(Debug)]
+struct Snapshot {
+    time: DateTime<Utc>,
+    path: String,
+    files: Vec<String>,
+}
+
+/// The snapshots and files that fell out of a [`RollingWindow`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub(crate) struct Expired {
+    /// The time and path of every expired snapshot
+    pub snapshots: Vec<(DateTime<Utc>, String)>,
+    /// The path of every segment, key or map that is no longer referenced by any snapshot
+    pub files: Vec<String>,
+}
+
+impl RollingWindow {
+    pub(crate) fn new(duration: Duration) -> Self {
+        Self {
+            duration: TimeDelta::from_std(duration).unwrap_or(TimeDelta::MAX),
+            snapshots: VecDeque::new(),
+            file_refs: HashMap::new(),
+        }
+    }
+
+    /// Add a new snapshot referencing the given segments, keys and maps,
+    /// and remove all snapshots that are older than the window.
+    ///
+    /// The latest snapshot is always kept, even if it's older than the window.
+    pub(crate) fn push(
+        &mut self,
+        time: DateTime<Utc>,
+        path: String,
+        mut files: Vec<String>,
+    ) -> Expired {
+        // A key or map can be used by multiple segments of the same snapshot
+        files.sort();
+        files.dedup();
+        for file in &files {
+            *self.file_refs.entry(file.clone()).or_default() += 1;
+        }
+        self.snapshots.push_back(Snapshot { time, path, files });
+        let mut expired = Expired::default();
+        let window_start = time.checked_sub_signed(self.duration);
+        while self.snapshots.len() > 1
+            && let Some(snapshot) = self.snapshots.front()
+            && window_start.is_some_and(|window_start| snapshot.time < window_start)
+        {
+            let snapshot = self.snapshots.pop_front().unwrap();
+            for file in snapshot.files {
+                let refs = self.file_refs.get_mut(&file).unwrap();
+                *refs -= 1;
+                if *refs == 0 {
+                    self.file_refs.remove(&file);
+                    expired.files.push(file);
+                }
+            }
+            expired.snapshots.push((snapshot.time, snapshot.path));
+        }
+        expired
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expire_old_snapshots_and_files() {
+        let start = Utc::now();
+        let at = |secs| start + TimeDelta::seconds(secs);
+        let files = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let mut window = RollingWindow::new(Duration::from_secs(10));
+        assert_eq!(
+            window.push(at(0), "a.m3u8".into(), files(&["1.ts", "2.ts"])),
+            Expired::default()
+        );
+        assert_eq!(
+            window.push(at(6), "b.m3u8".into(), files(&["2.ts", "3.ts"])),
+            Expired::default()
+        );
+        assert_eq!(
+            window.push(at(12), "c.m3u8".into(), files(&["3.ts", "4.ts"])),
+            Expired {
+                snapshots: vec![(at(0), "a.m3u8".into())],
+                files: files(&["1.ts"]),
+            }
+        );
+        assert_eq!(
+            window.push(at(30), "d.m3u8".into(), files(&["5.ts"])),
+            Expired {
+                snapshots: vec![(at(6), "b.m3u8".into()), (at(12), "c.m3u8".into())],
+                files: files(&["2.ts", "3.ts", "4.ts"]),
+            }
+        );
+    }
+
+    #[test]
+    fn expire_keys_and_maps() {
+        let start = Utc::now();
+        let at = |secs| start + TimeDelta::seconds(secs);
+        let files = |names: &[&str]| names.iter().map(|name| name.to_string()).collect();
+        let mut window = RollingWindow::new(Duration::from_secs(10));
+        window.push(
+            at(0),
+            "a.m3u8".into(),
+            files(&["init.mp4", "key1.key", "1.m4s"]),
+        );
+        // The key rotates, but the map is still used
+        window.push(
+            at(6),
+            "b.m3u8".into(),
+            files(&["init.mp4", "key2.key", "2.m4s"]),
+        );
+        assert_eq!(
+            window.push(
+                at(12),
+                "c.m3u8".into(),
+                files(&["init.mp4", "key2.key", "3.m4s"])
+            ),
+            Expired {
+                snapshots: vec![(at(0), "a.m3u8".into())],
+                files: files(&["1.m4s", "key1.key"]),
+            }
+        );
+    }
+}
@@ -0,0 +1,106 @@
+use std::sync::{Arc, RwLock};
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::Serialize;
+use tracing::info;
+use warp::filters::BoxedFilter;
+use warp::{Filter, Reply, reply};
+
+/// When the global replay clock starts.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum ClockStart {
+    /// Start the clock when the server starts.
+    #[default]
+    Server,
+    /// Start the clock with a `POST /control/clock/start` request.
+    Manual,
+}
+
+/// The clock shared by all clients when replaying with [`SessionMode::Global`].
+///
+/// [`SessionMode::Global`]: crate::replay::SessionMode::Global
+#[derive(Debug)]
+pub(crate) struct ReplayClock {
+    start: RwLock<Option<DateTime<Utc>>>,
+}
+
+#[derive(Debug, Serialize)]
+struct ClockStatus {
+    started: bool,
+    start: Option<DateTime<Utc>>,
+    /// The current position in the recording, in milliseconds.
+    position: i64,
+}
+
+impl ReplayClock {
+    pub(crate) fn new(clock_start: ClockStart) -> Self {
+        let start = match clock_start {
+            ClockStart::Server => Some(Utc::now()),
+            ClockStart::Manual => None,
+        };
+        Self {
+            start: RwLock::new(start),
+        }
+    }
+
+    /// The start time of the clock in milliseconds.
+    ///
+    /// While the clock is stopped, this is always the current time,
+    /// so clients keep seeing the start of the recording.
+    pub(crate) fn start_millis(&self) -> i64 {
+        self.start
+            .read()
+            .unwrap()
+            .unwrap_or_else(Utc::now)
+            .timestamp_millis()
+    }
+
+    fn start(&self) {
+        let now = Utc::now();
+        info!("Starting replay clock at {now}");
+        *self.start.write().unwrap() = Some(now);
+    }
+
+    fn reset(&self) {
+        info!("Resetting replay clock");
+        *self.start.write().unwrap() = None;
+    }
+
+    fn status(&self) -> ClockStatus {
+        let start = *self.start.read().unwrap();
+        ClockStatus {
+            started: start.is_some(),
+            start,
+            position: start.map_or(0, |start| (Utc::now() - start).num_milliseconds()),
+        }
+    }
+}
+
+/// Routes to inspect and control the global replay clock:
+///
+/// * `GET /control/clock` returns the status of the clock.
+/// * `POST /control/clock/start` (re)starts the clock from the start of the recording.
+/// * `POST /control/clock/reset` stops the clock, and goes back to the start of the recording.
+pub(crate) fn control_routes(clock: Arc<ReplayClock>) -> BoxedFilter<(reply::Response,)> {
+    let status = warp::path!("control" / "clock").and(warp::get()).map({
+        let clock = clock.clone();
+        move || reply::json(&clock.status()).into_response()
+    });
+    let start = warp::path!("control" / "clock" / "start")
+        .and(warp::post())
+        .map({
+            let clock = clock.clone();
+            move || {
+                clock.start();
+                reply::json(&clock.status()).into_response()
+            }
+        });
+    let reset = warp::path!("control" / "clock" / "reset")
+        .and(warp::post())
+        .map(move || {
+            clock.reset();
+            reply::json(&clock.status()).into_response()
+        });
+    status.or(start).unify().or(reset).unify().boxed()
+}
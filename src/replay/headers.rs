@@ -0,0 +1,82 @@
+use warp::http::header::{
+    CONNECTION, CONTENT_LENGTH, CONTENT_RANGE, DATE, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION,
+    SET_COOKIE, STRICT_TRANSPORT_SECURITY, TE, TRAILER, TRANSFER_ENCODING, UPGRADE,
+};
+use warp::http::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::replay::ReplayOptions;
+use crate::shared::FileRecord;
+
+/// Recorded headers that are never replayed, since they only apply to the original
+/// connection, response body, response time or origin.
+const SKIPPED_HEADERS: [HeaderName; 13] = [
+    CONNECTION,
+    HeaderName::from_static("keep-alive"),
+    PROXY_AUTHENTICATE,
+    PROXY_AUTHORIZATION,
+    TE,
+    TRAILER,
+    TRANSFER_ENCODING,
+    UPGRADE,
+    CONTENT_LENGTH,
+    CONTENT_RANGE,
+    DATE,
+    // Would set the cookies of the original origin on the replay server,
+    // or force browsers to use HTTPS for localhost
+    SET_COOKIE,
+    STRICT_TRANSPORT_SECURITY,
+];
+
+/// Replace the headers of a response with the recorded response headers of a file,
+/// and then apply the header overrides from the replay options.
+pub(crate) fn apply_headers(
+    headers: &mut HeaderMap,
+    file: Option<&FileRecord>,
+    options: &ReplayOptions,
+) {
+    let mut recorded = HeaderMap::new();
+    for (name, value) in file.map(|file| file.headers.as_slice()).unwrap_or_default() {
+        let (Ok(name), Ok(value)) = (
+            HeaderName::from_bytes(name.as_bytes()),
+            HeaderValue::from_str(value),
+        ) else {
+            continue;
+        };
+        if SKIPPED_HEADERS.contains(&name) {
+            continue;
+        }
+        recorded.append(name, value);
+    }
+    // Replaces any existing values with the same name
+    headers.extend(recorded);
+    for (name, value) in &options.headers {
+        headers.insert(name, value.clone());
+    }
+    for name in &options.drop_headers {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_origin_headers() {
+        let file = FileRecord {
+            headers: vec![
+                ("content-type".to_string(), "video/mp2t".to_string()),
+                ("set-cookie".to_string(), "session=abc".to_string()),
+                (
+                    "strict-transport-security".to_string(),
+                    "max-age=31536000".to_string(),
+                ),
+            ],
+            ..Default::default()
+        };
+        let mut headers = HeaderMap::new();
+        apply_headers(&mut headers, Some(&file), &ReplayOptions::default());
+        assert_eq!(headers.len(), 1);
+        assert_eq!(headers["content-type"], "video/mp2t");
+    }
+}
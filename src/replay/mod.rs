@@ -1,32 +1,73 @@
 use anyhow::anyhow;
+use std::collections::{HashMap, HashSet};
 use std::convert::Infallible;
 use std::fmt::Write;
-use std::net::{IpAddr, SocketAddr};
+use std::net::IpAddr;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
 
-use chrono::{DateTime, TimeZone, Utc};
-use m3u8_rs::Playlist;
+use chrono::{DateTime, TimeDelta, TimeZone, Utc};
+use m3u8_rs::{MediaPlaylist, Playlist};
 use serde::{Deserialize, Serialize};
 use tokio::fs;
 use tokio::io;
 use tokio::io::AsyncReadExt;
-use tokio::net::TcpListener;
 use tokio_util::sync::CancellationToken;
-use warp::http::{Response, StatusCode, Uri, header::CONTENT_TYPE};
+use tracing::{error, warn};
+use warp::filters::BoxedFilter;
+use warp::http::header::{CONTENT_TYPE, SET_COOKIE};
+use warp::http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode, Uri};
 use warp::path::{FullPath, Peek, Tail};
 use warp::reject::{Reject, custom};
 use warp::{Filter, Rejection, Reply, reject, reply};
 
 use crate::record::strip_media_playlist;
-use crate::shared::{Recording, StripBom};
+use crate::shared::{Recording, RecordingMetadata, StripBom};
+pub use clock::ClockStart;
+use clock::{ReplayClock, control_routes};
+use headers::apply_headers;
+pub use server::{ReplayHandle, ReplayServer};
+use session::SESSION_COOKIE;
+pub use session::SessionMode;
+pub use synthesize::{DvrOptions, SimulateLiveOptions};
+use synthesize::{SegmentHistory, simulate_live};
+pub use tls::TlsOptions;
+
+mod clock;
+mod headers;
+mod server;
+mod session;
+mod synthesize;
+mod tls;
 
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 struct PlaylistQueryParams {
     start: Option<i64>,
 }
 
+#[derive(Debug, Default, Clone)]
+pub struct ReplayOptions {
+    /// Extra headers to add to every response, replacing recorded headers with the same name.
+    pub headers: HeaderMap,
+    /// Headers to remove from every response.
+    pub drop_headers: Vec<HeaderName>,
+    /// Serve over HTTPS instead of HTTP, with support for HTTP/2.
+    pub tls: Option<TlsOptions>,
+    /// How to keep track of the start time of each client.
+    pub session: SessionMode,
+    /// When to start the shared clock, if `session` is [`SessionMode::Global`].
+    pub clock_start: ClockStart,
+    /// Shift all program date times and date ranges by the offset between the recording
+    /// and the replay, so they match the wall clock of the replay.
+    pub rewrite_pdt: bool,
+    /// Replay VOD playlists as live playlists.
+    pub simulate_live: Option<SimulateLiveOptions>,
+    /// Replay live playlists with a DVR window, using all segments recorded so far.
+    pub dvr: Option<DvrOptions>,
+}
+
 #[derive(thiserror::Error, Debug)]
 #[non_exhaustive]
 pub enum ReplayError {
@@ -42,98 +83,399 @@ pub enum ReplayError {
     MissingPlaylist(PathBuf),
     #[error("Invalid playlist: {0}")]
     InvalidPlaylist(#[from] anyhow::Error),
+    #[error("TLS error: {0}")]
+    Tls(#[source] anyhow::Error),
+    #[error("Cannot simulate a live stream with live recording {0}")]
+    SimulateLiveRecording(String),
     #[error("Cancelled")]
     Cancelled,
 }
 
+/// A recording that is being replayed.
+#[derive(Debug)]
+struct ReplayedRecording {
+    /// The name of the recording, used in its URL when replaying multiple recordings.
+    name: String,
+    path: PathBuf,
+    recording: Recording,
+    start: DateTime<Utc>,
+    /// The segment history of each media playlist, only loaded when replaying with a DVR window.
+    histories: HashMap<String, SegmentHistory>,
+}
+
+/// Information about a replayed recording, as listed by the index endpoint.
+#[derive(Debug, Serialize)]
+struct RecordingInfo {
+    name: String,
+    url: String,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    playlists: Vec<String>,
+    metadata: RecordingMetadata,
+}
+
+/// Replay one or more recordings, until `token` is cancelled.
+///
+/// If `recording_paths` contains a single recording, it is served at the root of the server.
+/// Otherwise, every recording is served under `/rec/<name>/`, and `/rec/` lists all recordings.
+/// A path can also be a directory containing multiple recordings.
+///
+/// Use a [`ReplayServer`] to find out the address of the server, or to stop it gracefully.
 pub async fn replay(
-    recording_path: &Path,
+    recording_paths: &[PathBuf],
     address: IpAddr,
     port: u16,
+    options: ReplayOptions,
     token: CancellationToken,
 ) -> Result<(), ReplayError> {
-    let recording_path = recording_path.to_owned();
-    let raw_recording = fs::read_to_string(recording_path.join("recording.json"))
-        .await
-        .map_err(|_| ReplayError::MissingRecording)?;
-    let recording = serde_json::from_str::<Recording>(&raw_recording)?;
-    let recording_start = *recording
-        .earliest_time()
-        .ok_or(ReplayError::MissingStartTime)?;
+    let server = ReplayServer::new(recording_paths.to_vec())
+        .address(address)
+        .port(port)
+        .options(options)
+        .start()
+        .await?;
+    server.run_until_cancelled(token).await
+}
 
-    let segments = warp::fs::dir(recording_path.clone());
+/// Build the routes to replay the given recordings.
+async fn replay_routes(
+    recording_paths: &[PathBuf],
+    options: ReplayOptions,
+) -> Result<BoxedFilter<(reply::Response,)>, ReplayError> {
+    let single_recording = matches!(recording_paths, [path] if is_recording(path).await);
+    let mut recordings = find_recordings(recording_paths).await?;
+    if options.simulate_live.is_some()
+        && let Some(recording) = recordings.iter().find(|recording| recording.is_live())
+    {
+        return Err(ReplayError::SimulateLiveRecording(recording.name.clone()));
+    }
+    // Load all segment histories up front, instead of while serving the first requests
+    if options.dvr.is_some() {
+        for recording in &mut recordings {
+            recording.load_histories().await?;
+        }
+    }
 
-    let recording = Arc::new(recording);
-    let recording_path = Arc::new(recording_path);
+    let clock = Arc::new(ReplayClock::new(options.clock_start));
+    let session = options.session;
+    let options = Arc::new(options);
+    let routes = if single_recording {
+        let recording = recordings.into_iter().next().unwrap();
+        recording_routes(Arc::new(recording), options, "/".into(), clock.clone())
+    } else {
+        let index = recordings
+            .iter()
+            .map(|recording| RecordingInfo {
+                name: recording.name.clone(),
+                url: format!("/rec/{}/{}", recording.name, recording.main_playlist()),
+                start: recording.start,
+                end: *recording
+                    .recording
+                    .latest_time()
+                    .unwrap_or(&recording.start),
+                playlists: recording
+                    .recording
+                    .playlist_names()
+                    .map(String::from)
+                    .collect(),
+                metadata: recording.recording.metadata().clone(),
+            })
+            .collect::<Vec<_>>();
+        let index = Arc::new(index);
+        let index = warp::path("rec")
+            .and(warp::path::end())
+            .map(move || reply::json(&*index).into_response())
+            .boxed();
+        recordings.into_iter().fold(index, |routes, recording| {
+            let prefix = warp::path("rec").and(warp::path(recording.name.clone()));
+            let base_path = format!("/rec/{}/", recording.name);
+            let recording = prefix.and(recording_routes(
+                Arc::new(recording),
+                options.clone(),
+                base_path,
+                clock.clone(),
+            ));
+            routes.or(recording).unify().boxed()
+        })
+    };
+    let routes = if session == SessionMode::Global {
+        control_routes(clock).or(routes).unify().boxed()
+    } else {
+        routes
+    };
+    Ok(routes)
+}
 
-    let with_start = warp::path::tail()
-        .and(warp::query::<PlaylistQueryParams>())
-        .and_then(move |tail: Tail, params: PlaylistQueryParams| {
-            let file_name = tail.as_str().to_string();
-            let recording = recording.clone();
-            let recording_path = recording_path.clone();
-            async move {
-                let start = params.start.ok_or_else(reject)?;
-                let absolute_path = playlist_path_at_time(
-                    &file_name,
-                    recording,
-                    recording_start,
-                    &recording_path,
-                    start,
-                )
-                .ok_or_else(|| custom(ServerError::PlaylistNotFound(file_name)))?;
-                let reply = m3u8_reply(&absolute_path, start)
-                    .await
-                    .map_err(|e| custom(ServerError::PlaylistFileError(e)))?
-                    .into_response();
-                Ok::<reply::Response, Rejection>(reply)
+impl ReplayedRecording {
+    async fn load(path: &Path, name: String) -> Result<Self, ReplayError> {
+        let raw_recording = fs::read_to_string(path.join("recording.json"))
+            .await
+            .map_err(|_| ReplayError::MissingRecording)?;
+        let recording = serde_json::from_str::<Recording>(&raw_recording)?;
+        let start = *recording
+            .earliest_time()
+            .ok_or(ReplayError::MissingStartTime)?;
+        Ok(Self {
+            name,
+            path: path.to_owned(),
+            recording,
+            start,
+            histories: HashMap::new(),
+        })
+    }
+
+    /// Load the segment history of every media playlist.
+    async fn load_histories(&mut self) -> Result<(), ReplayError> {
+        for playlist_name in self.recording.playlist_names() {
+            let snapshots = self.recording.snapshots(playlist_name);
+            if let Some(history) = SegmentHistory::load(&self.path, snapshots).await? {
+                self.histories.insert(playlist_name.to_string(), history);
             }
-        });
-    let without_start =
-        warp::path::full()
-            .and(no_playlist_params())
-            .map(move |full_path: FullPath| {
-                let now = Utc::now();
-                let client_start = now.timestamp_millis();
-                let redirect_uri = format!("{}?start={}", full_path.as_str(), client_start);
-                warp::redirect::temporary(Uri::from_str(&redirect_uri).unwrap())
-            });
-    let playlist = path_extension(".m3u8").and(with_start.or(without_start));
+        }
+        Ok(())
+    }
 
-    let cors = warp::cors().allow_any_origin().build();
+    /// Whether this is a recording of a live stream, i.e. some playlist has multiple snapshots.
+    fn is_live(&self) -> bool {
+        self.recording
+            .playlist_names()
+            .any(|name| self.recording.snapshots(name).nth(1).is_some())
+    }
 
-    let service = playlist.or(segments).with(cors).recover(handle_rejection);
+    /// The name of the playlist to start playing, i.e. the master playlist if there is one.
+    fn main_playlist(&self) -> &str {
+        let mut names = self.recording.playlist_names();
+        if self.recording.earliest_time_for("index.m3u8").is_some() {
+            "index.m3u8"
+        } else {
+            names.next().unwrap_or("index.m3u8")
+        }
+    }
+}
 
-    let address = SocketAddr::from((address, port));
-    let listener = TcpListener::bind(address)
+async fn is_recording(path: &Path) -> bool {
+    fs::try_exists(path.join("recording.json"))
         .await
-        .map_err(ReplayError::Bind)?;
-    let server = warp::serve(service).incoming(listener);
-    println!("Replay server listening on http://{address}/");
+        .unwrap_or(false)
+}
 
-    token
-        .run_until_cancelled(server.run())
-        .await
-        .ok_or(ReplayError::Cancelled)?;
+/// Load all recordings from the given paths.
+///
+/// Each path is either a recording, or a directory containing recordings.
+/// Invalid recordings inside a directory are skipped.
+async fn find_recordings(paths: &[PathBuf]) -> Result<Vec<ReplayedRecording>, ReplayError> {
+    let mut recordings = Vec::<ReplayedRecording>::new();
+    let mut names = HashSet::new();
+    let mut unique_name = |path: &Path| {
+        let base_name = path.file_name().map_or("recording".into(), |name| {
+            name.to_string_lossy().to_string()
+        });
+        let mut name = base_name.clone();
+        let mut suffix = 1;
+        while !names.insert(name.clone()) {
+            suffix += 1;
+            name = format!("{base_name}-{suffix}");
+        }
+        name
+    };
+    for path in paths {
+        if is_recording(path).await {
+            recordings.push(ReplayedRecording::load(path, unique_name(path)).await?);
+            continue;
+        }
+        let mut entries = fs::read_dir(path)
+            .await
+            .map_err(|_| ReplayError::MissingRecording)?;
+        let mut subdirs = Vec::new();
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .map_err(|_| ReplayError::MissingRecording)?
+        {
+            if is_recording(&entry.path()).await {
+                subdirs.push(entry.path());
+            }
+        }
+        subdirs.sort();
+        for subdir in subdirs {
+            match ReplayedRecording::load(&subdir, unique_name(&subdir)).await {
+                Ok(recording) => recordings.push(recording),
+                Err(e) => warn!("Skipping recording {}: {e}", subdir.display()),
+            }
+        }
+    }
+    if recordings.is_empty() {
+        return Err(ReplayError::MissingRecording);
+    }
+    Ok(recordings)
+}
+
+/// Build the routes to replay a single recording, which is served under `base_path`.
+fn recording_routes(
+    recording: Arc<ReplayedRecording>,
+    options: Arc<ReplayOptions>,
+    base_path: String,
+    clock: Arc<ReplayClock>,
+) -> BoxedFilter<(reply::Response,)> {
+    let segments = warp::fs::dir(recording.path.clone())
+        .map({
+            let recording = recording.clone();
+            let options = options.clone();
+            move |file: warp::fs::File| {
+                let path = file.path().strip_prefix(&recording.path).ok();
+                let file_record =
+                    path.and_then(|path| recording.recording.file(&path.to_string_lossy()));
+                let mut response = file.into_response();
+                apply_headers(response.headers_mut(), file_record, &options);
+                response
+            }
+        })
+        .boxed();
 
-    Ok(())
+    let session = options.session;
+    let playlist = move |file_name: String, start: i64| {
+        let recording = recording.clone();
+        let options = options.clone();
+        async move {
+            let recording_time = recording_time(recording.start, start)
+                .ok_or_else(|| custom(ServerError::PlaylistNotFound(file_name.clone())))?;
+            let relative_path =
+                playlist_path_at_time(&file_name, &recording.recording, recording_time)
+                    .ok_or_else(|| custom(ServerError::PlaylistNotFound(file_name.clone())))?;
+            // Synthesize a DVR playlist from the segment history, or load the recorded playlist
+            let dvr_playlist = options.dvr.as_ref().and_then(|dvr| {
+                let history = recording.histories.get(&file_name)?;
+                history.playlist_at(recording_time, dvr)
+            });
+            let playlist = match dvr_playlist {
+                Some(playlist) => Playlist::MediaPlaylist(playlist),
+                None => load_playlist(&recording.path.join(relative_path))
+                    .await
+                    .map_err(|e| custom(ServerError::PlaylistFileError(e)))?,
+            };
+            let rewrite = PlaylistRewrite {
+                // Only the query parameter must be passed on to the media playlists
+                query_start: (session == SessionMode::Query).then_some(start),
+                pdt_offset: options
+                    .rewrite_pdt
+                    .then(|| replay_offset(recording.start, start))
+                    .flatten(),
+                // The final snapshot of an ended live recording also has an end list,
+                // but it must still be replayed as recorded
+                simulate_live: options
+                    .simulate_live
+                    .as_ref()
+                    .filter(|_| !recording.is_live())
+                    .map(|simulate_live| (simulate_live, session_position(start))),
+            };
+            let mut reply = m3u8_reply(playlist, &rewrite);
+            apply_headers(
+                reply.headers_mut(),
+                recording.recording.file(relative_path),
+                &options,
+            );
+            Ok::<reply::Response, Rejection>(reply)
+        }
+    };
+
+    match session {
+        SessionMode::Query => {
+            let with_start = warp::path::tail().and(query_start()).and_then({
+                let playlist = playlist.clone();
+                move |tail: Tail, start: i64| playlist(tail.as_str().to_string(), start)
+            });
+            let without_start =
+                warp::path::full()
+                    .and(no_playlist_params())
+                    .map(move |full_path: FullPath| {
+                        let client_start = Utc::now().timestamp_millis();
+                        let redirect_uri = format!("{}?start={}", full_path.as_str(), client_start);
+                        warp::redirect::temporary(Uri::from_str(&redirect_uri).unwrap())
+                            .into_response()
+                    });
+            path_extension(".m3u8")
+                .and(with_start.or(without_start).unify())
+                .or(segments)
+                .unify()
+                .boxed()
+        }
+        SessionMode::Path => {
+            let session_start = warp::path("s").and(warp::path::param::<i64>());
+            let with_session = session_start
+                .and(path_extension(".m3u8"))
+                .and(warp::path::tail())
+                .and_then(move |start: i64, tail: Tail| playlist(tail.as_str().to_string(), start));
+            // Also serve segments in the session, since their URIs are relative to the playlist
+            let session_segments = session_start
+                .and(segments.clone())
+                .map(|_start: i64, response: reply::Response| response);
+            let without_session =
+                path_extension(".m3u8")
+                    .and(warp::path::tail())
+                    .map(move |tail: Tail| {
+                        let client_start = Utc::now().timestamp_millis();
+                        let redirect_uri = format!("{base_path}s/{client_start}/{}", tail.as_str());
+                        warp::redirect::temporary(Uri::from_str(&redirect_uri).unwrap())
+                            .into_response()
+                    });
+            with_session
+                .or(session_segments)
+                .unify()
+                .or(without_session)
+                .unify()
+                .or(segments)
+                .unify()
+                .boxed()
+        }
+        SessionMode::Cookie => path_extension(".m3u8")
+            .and(warp::path::tail())
+            .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+            .and_then(move |tail: Tail, cookie: Option<String>| {
+                let playlist = playlist.clone();
+                let base_path = base_path.clone();
+                async move {
+                    let client_start = cookie.and_then(|cookie| cookie.parse::<i64>().ok());
+                    let start = client_start.unwrap_or_else(|| Utc::now().timestamp_millis());
+                    let mut reply = playlist(tail.as_str().to_string(), start).await?;
+                    if client_start.is_none() {
+                        let cookie = format!("{SESSION_COOKIE}={start}; Path={base_path}");
+                        reply
+                            .headers_mut()
+                            .append(SET_COOKIE, HeaderValue::from_str(&cookie).unwrap());
+                    }
+                    Ok::<reply::Response, Rejection>(reply)
+                }
+            })
+            .or(segments)
+            .unify()
+            .boxed(),
+        SessionMode::Global => path_extension(".m3u8")
+            .and(warp::path::tail())
+            .and_then(move |tail: Tail| playlist(tail.as_str().to_string(), clock.start_millis()))
+            .or(segments)
+            .unify()
+            .boxed(),
+    }
 }
 
-fn playlist_path_at_time(
-    playlist_name: &str,
-    recording: Arc<Recording>,
-    recording_start: DateTime<Utc>,
-    recording_path: &Path,
-    client_start: i64,
-) -> Option<PathBuf> {
+/// The current time in the recording, for a client that started at `client_start`.
+fn recording_time(recording_start: DateTime<Utc>, client_start: i64) -> Option<DateTime<Utc>> {
     // At T = client_start + X, serve the playlist at recording_start + X
     let client_start = Utc.timestamp_millis_opt(client_start).single()?;
     let offset = Utc::now() - client_start;
-    let recording_time = recording_start + offset;
+    Some(recording_start + offset)
+}
+
+/// Find the path of the playlist to serve, relative to the recording.
+fn playlist_path_at_time<'a>(
+    playlist_name: &str,
+    recording: &'a Recording,
+    recording_time: DateTime<Utc>,
+) -> Option<&'a str> {
     let (_, relative_path) = recording
         .find_latest_before(playlist_name, recording_time)
         .or_else(|| recording.earliest_time_for(playlist_name))?;
-    Some(recording_path.join(relative_path))
+    Some(relative_path)
 }
 
 fn path_extension(ext: &'static str) -> impl Filter<Extract = (), Error = Rejection> + Clone {
@@ -148,6 +490,11 @@ fn path_extension(ext: &'static str) -> impl Filter<Extract = (), Error = Reject
         .untuple_one()
 }
 
+fn query_start() -> impl Filter<Extract = (i64,), Error = Rejection> + Clone {
+    warp::query::<PlaylistQueryParams>()
+        .and_then(|params: PlaylistQueryParams| async move { params.start.ok_or_else(reject) })
+}
+
 fn no_playlist_params() -> impl Filter<Extract = (), Error = Rejection> + Clone {
     warp::query::<PlaylistQueryParams>()
         .and_then(|params: PlaylistQueryParams| async move {
@@ -160,7 +507,48 @@ fn no_playlist_params() -> impl Filter<Extract = (), Error = Rejection> + Clone
         .untuple_one()
 }
 
-async fn m3u8_reply(path: &Path, start: i64) -> Result<impl Reply + use<>, ReplayError> {
+/// The offset between the recording and its replay by a client that started at `client_start`.
+fn replay_offset(recording_start: DateTime<Utc>, client_start: i64) -> Option<TimeDelta> {
+    let client_start = Utc.timestamp_millis_opt(client_start).single()?;
+    Some(client_start - recording_start)
+}
+
+/// The time since a client started at `client_start`.
+fn session_position(client_start: i64) -> Duration {
+    let client_start = Utc.timestamp_millis_opt(client_start).single();
+    client_start
+        .and_then(|client_start| (Utc::now() - client_start).to_std().ok())
+        .unwrap_or_default()
+}
+
+/// Shift all program date times and date ranges of a media playlist by the given offset.
+fn shift_dates(playlist: &mut MediaPlaylist, offset: TimeDelta) {
+    for segment in &mut playlist.segments {
+        if let Some(program_date_time) = segment.program_date_time.as_mut() {
+            *program_date_time += offset;
+        }
+        if let Some(daterange) = segment.daterange.as_mut() {
+            daterange.start_date += offset;
+            if let Some(end_date) = daterange.end_date.as_mut() {
+                *end_date += offset;
+            }
+        }
+    }
+}
+
+/// How to rewrite a playlist before serving it.
+#[derive(Debug, Default)]
+struct PlaylistRewrite<'a> {
+    /// Add this start time to the variant and rendition URIs of a master playlist.
+    query_start: Option<i64>,
+    /// Shift all dates in a media playlist by this offset.
+    pdt_offset: Option<TimeDelta>,
+    /// Turn a VOD media playlist into a live playlist at this position in the session.
+    simulate_live: Option<(&'a SimulateLiveOptions, Duration)>,
+}
+
+/// Load a playlist from a recording.
+async fn load_playlist(path: &Path) -> Result<Playlist, ReplayError> {
     let mut file = fs::File::open(&path)
         .await
         .map_err(|_| ReplayError::MissingPlaylist(path.to_owned()))?;
@@ -170,37 +558,51 @@ async fn m3u8_reply(path: &Path, start: i64) -> Result<impl Reply + use<>, Repla
         .await
         .map_err(|e| ReplayError::InvalidPlaylist(anyhow!(e)))?;
     (&mut raw_playlist).strip_bom();
-    let mut playlist = m3u8_rs::parse_playlist_res(&raw_playlist).map_err(|e| {
+    m3u8_rs::parse_playlist_res(&raw_playlist).map_err(|e| {
         ReplayError::InvalidPlaylist(anyhow!(
             e.map_input(|i| String::from_utf8_lossy(i).to_string())
         ))
-    })?;
+    })
+}
+
+/// Rewrite a playlist, and create a response to serve it.
+fn m3u8_reply(mut playlist: Playlist, rewrite: &PlaylistRewrite<'_>) -> reply::Response {
     // Rewrite the playlist
     match &mut playlist {
         Playlist::MasterPlaylist(playlist) => {
             // Rewrite the variant and media URLs
-            for variant in playlist.variants.iter_mut() {
-                write!(&mut variant.uri, "?start={start}").unwrap();
-            }
-            for media in playlist.alternatives.iter_mut() {
-                if let Some(uri) = media.uri.as_mut() {
-                    write!(uri, "?start={start}").unwrap();
+            if let Some(start) = rewrite.query_start {
+                for variant in playlist.variants.iter_mut() {
+                    write!(&mut variant.uri, "?start={start}").unwrap();
+                }
+                for media in playlist.alternatives.iter_mut() {
+                    if let Some(uri) = media.uri.as_mut() {
+                        write!(uri, "?start={start}").unwrap();
+                    }
                 }
             }
         }
         Playlist::MediaPlaylist(playlist) => {
             // Strip tags with original playlist information
             strip_media_playlist(playlist);
+            if let Some((options, position)) = rewrite.simulate_live
+                && playlist.end_list
+            {
+                simulate_live(playlist, options, position);
+            }
+            if let Some(offset) = rewrite.pdt_offset {
+                shift_dates(playlist, offset);
+            }
         }
     }
-    raw_playlist.clear();
+    let mut raw_playlist = Vec::new();
     playlist.write_to(&mut raw_playlist).unwrap();
     // Create a response
-    let response = Response::builder()
+    Response::builder()
         .header(CONTENT_TYPE, "application/x-mpegurl")
         .body(raw_playlist)
-        .unwrap();
-    Ok(response)
+        .unwrap()
+        .into_response()
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -225,7 +627,7 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
         message = e.to_string();
         code = StatusCode::INTERNAL_SERVER_ERROR;
     } else {
-        eprintln!("Unhandled rejection: {:?}", err);
+        error!("Unhandled rejection: {:?}", err);
         code = StatusCode::INTERNAL_SERVER_ERROR;
         message = "Internal server error".to_owned();
     }
@@ -233,3 +635,363 @@ async fn handle_rejection(err: Rejection) -> Result<impl Reply, Infallible> {
     let html = warp::reply::html(message);
     Ok(warp::reply::with_status(html, code))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use m3u8_rs::{DateRange, MediaSegment};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dest =
+            std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dest);
+        dest
+    }
+
+    /// Write a recording of a live stream, with a master playlist
+    /// and two snapshots of its media playlist 4 seconds apart.
+    fn write_live_recording(dir: &Path) {
+        std::fs::create_dir_all(dir).unwrap();
+        let files = [
+            (
+                "index.m3u8",
+                "#EXTM3U\n#EXT-X-STREAM-INF:BANDWIDTH=1000000\nlive.m3u8\n",
+            ),
+            (
+                "live-0.m3u8",
+                "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXTINF:4.0,\nsegment-0.ts\n#EXTINF:4.0,\nsegment-1.ts\n",
+            ),
+            (
+                "live-1.m3u8",
+                "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:1\n#EXTINF:4.0,\nsegment-1.ts\n#EXTINF:4.0,\nsegment-2.ts\n",
+            ),
+            ("segment-0.ts", "segment"),
+            ("segment-1.ts", "segment"),
+            ("segment-2.ts", "segment"),
+        ];
+        for (name, contents) in files {
+            std::fs::write(dir.join(name), contents).unwrap();
+        }
+        let start = Utc.with_ymd_and_hms(2026, 10, 18, 20, 0, 0).unwrap();
+        let mut recording = Recording::new();
+        recording.add(start, "index.m3u8", "index.m3u8".to_string());
+        recording.add(start, "live.m3u8", "live-0.m3u8".to_string());
+        recording.add(
+            start + TimeDelta::seconds(4),
+            "live.m3u8",
+            "live-1.m3u8".to_string(),
+        );
+        std::fs::write(
+            dir.join("recording.json"),
+            serde_json::to_string(&recording).unwrap(),
+        )
+        .unwrap();
+    }
+
+    /// The start time of a client that started `seconds` ago.
+    fn started_ago(seconds: i64) -> i64 {
+        (Utc::now() - TimeDelta::seconds(seconds)).timestamp_millis()
+    }
+
+    fn body(response: &Response<warp::hyper::body::Bytes>) -> String {
+        String::from_utf8_lossy(response.body()).to_string()
+    }
+
+    #[tokio::test]
+    async fn multiple_recordings() {
+        let dir = temp_dir("replay-multiple");
+        write_live_recording(&dir.join("first"));
+        write_live_recording(&dir.join("second"));
+        // An invalid recording in the directory is skipped
+        std::fs::create_dir_all(dir.join("invalid")).unwrap();
+        std::fs::write(dir.join("invalid").join("recording.json"), "{}").unwrap();
+        let routes = replay_routes(std::slice::from_ref(&dir), ReplayOptions::default())
+            .await
+            .unwrap();
+
+        let response = warp::test::request().path("/rec/").reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let index: serde_json::Value = serde_json::from_slice(response.body()).unwrap();
+        let index = index.as_array().unwrap();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index[0]["name"], "first");
+        assert_eq!(index[0]["url"], "/rec/first/index.m3u8");
+        assert_eq!(index[0]["end"], "2026-10-18T20:00:04Z");
+        assert_eq!(
+            index[0]["playlists"],
+            serde_json::json!(["index.m3u8", "live.m3u8"])
+        );
+        assert_eq!(index[1]["name"], "second");
+
+        // Every recording is served under its own path
+        let response = warp::test::request()
+            .path("/rec/second/live.m3u8")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(
+            location.starts_with("/rec/second/live.m3u8?start="),
+            "{location}"
+        );
+        let response = warp::test::request()
+            .path(&format!("/rec/second/live.m3u8?start={}", started_ago(5)))
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(&response).contains("segment-2.ts"));
+        let response = warp::test::request()
+            .path("/rec/first/segment-0.ts")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(&response), "segment");
+
+        for path in ["/live.m3u8?start=0", "/rec/invalid/index.m3u8?start=0"] {
+            let response = warp::test::request().path(path).reply(&routes).await;
+            assert_eq!(response.status(), StatusCode::NOT_FOUND, "{path}");
+        }
+    }
+
+    async fn live_routes(name: &str, options: ReplayOptions) -> BoxedFilter<(reply::Response,)> {
+        let dir = temp_dir(name);
+        write_live_recording(&dir);
+        replay_routes(&[dir], options).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn query_session() {
+        let routes = live_routes("replay-query", ReplayOptions::default()).await;
+        let response = warp::test::request()
+            .path("/index.m3u8")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(location.starts_with("/index.m3u8?start="), "{location}");
+        // The start time is passed on to the media playlists
+        let response = warp::test::request()
+            .path("/index.m3u8?start=123")
+            .reply(&routes)
+            .await;
+        assert!(body(&response).contains("live.m3u8?start=123"));
+        let response = warp::test::request()
+            .path(&format!("/live.m3u8?start={}", started_ago(0)))
+            .reply(&routes)
+            .await;
+        assert!(!body(&response).contains("segment-2.ts"));
+        let response = warp::test::request()
+            .path(&format!("/live.m3u8?start={}", started_ago(5)))
+            .reply(&routes)
+            .await;
+        assert!(body(&response).contains("segment-2.ts"));
+    }
+
+    #[tokio::test]
+    async fn path_session() {
+        let options = ReplayOptions {
+            session: SessionMode::Path,
+            ..Default::default()
+        };
+        let routes = live_routes("replay-path", options).await;
+        let response = warp::test::request()
+            .path("/index.m3u8")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+        let location = response.headers()["location"].to_str().unwrap();
+        assert!(location.starts_with("/s/"), "{location}");
+        assert!(location.ends_with("/index.m3u8"), "{location}");
+        // The variant URIs are relative, so they stay in the session
+        let response = warp::test::request().path(location).reply(&routes).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(body(&response).contains("\nlive.m3u8\n"));
+        let response = warp::test::request()
+            .path(&format!("/s/{}/live.m3u8", started_ago(5)))
+            .reply(&routes)
+            .await;
+        assert!(body(&response).contains("segment-2.ts"));
+        for path in ["/s/123/segment-2.ts", "/segment-2.ts"] {
+            let response = warp::test::request().path(path).reply(&routes).await;
+            assert_eq!(response.status(), StatusCode::OK, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn cookie_session() {
+        let options = ReplayOptions {
+            session: SessionMode::Cookie,
+            ..Default::default()
+        };
+        let routes = live_routes("replay-cookie", options).await;
+        // The session starts with the first playlist request
+        let response = warp::test::request()
+            .path("/index.m3u8")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response.headers()[SET_COOKIE].to_str().unwrap();
+        assert!(cookie.starts_with("streamrr_start="), "{cookie}");
+        assert!(cookie.ends_with("; Path=/"), "{cookie}");
+        assert!(body(&response).contains("\nlive.m3u8\n"));
+        let response = warp::test::request()
+            .path("/live.m3u8")
+            .header("cookie", format!("streamrr_start={}", started_ago(5)))
+            .reply(&routes)
+            .await;
+        assert!(!response.headers().contains_key(SET_COOKIE));
+        assert!(body(&response).contains("segment-2.ts"));
+    }
+
+    #[tokio::test]
+    async fn global_session() {
+        let options = ReplayOptions {
+            session: SessionMode::Global,
+            ..Default::default()
+        };
+        let routes = live_routes("replay-global", options).await;
+        // Every client shares the clock, which started with the server
+        for _ in 0..2 {
+            let response = warp::test::request()
+                .path("/live.m3u8")
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(!response.headers().contains_key(SET_COOKIE));
+            assert!(!body(&response).contains("segment-2.ts"));
+        }
+        let response = warp::test::request()
+            .path("/index.m3u8")
+            .reply(&routes)
+            .await;
+        assert!(body(&response).contains("\nlive.m3u8\n"));
+    }
+
+    #[tokio::test]
+    async fn control_clock() {
+        let options = ReplayOptions {
+            session: SessionMode::Global,
+            clock_start: ClockStart::Manual,
+            ..Default::default()
+        };
+        let routes = live_routes("replay-clock", options).await;
+        let clock = async |method: &str, path: &str| {
+            let response = warp::test::request()
+                .method(method)
+                .path(path)
+                .reply(&routes)
+                .await;
+            assert_eq!(response.status(), StatusCode::OK, "{method} {path}");
+            serde_json::from_slice::<serde_json::Value>(response.body()).unwrap()
+        };
+        let status = clock("GET", "/control/clock").await;
+        assert_eq!(status["started"], false);
+        assert_eq!(status["start"], serde_json::Value::Null);
+        assert_eq!(status["position"], 0);
+        // Until the clock starts, clients stay at the start of the recording
+        let response = warp::test::request()
+            .path("/live.m3u8")
+            .reply(&routes)
+            .await;
+        assert!(body(&response).contains("segment-0.ts"));
+
+        let before = Utc::now();
+        let status = clock("POST", "/control/clock/start").await;
+        assert_eq!(status["started"], true);
+        let start: DateTime<Utc> = serde_json::from_value(status["start"].clone()).unwrap();
+        assert!(start >= before && start <= Utc::now());
+        assert_eq!(clock("GET", "/control/clock").await["started"], true);
+        let status = clock("POST", "/control/clock/reset").await;
+        assert_eq!(status["started"], false);
+        assert_eq!(status["start"], serde_json::Value::Null);
+
+        // The clock can only be changed with POST
+        let response = warp::test::request()
+            .path("/control/clock/start")
+            .reply(&routes)
+            .await;
+        assert_ne!(response.status(), StatusCode::OK);
+        assert_eq!(clock("GET", "/control/clock").await["started"], false);
+    }
+
+    #[tokio::test]
+    async fn control_clock_only_for_global_session() {
+        let routes = live_routes("replay-no-clock", ReplayOptions::default()).await;
+        let response = warp::test::request()
+            .path("/control/clock")
+            .reply(&routes)
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn simulate_live_only_for_vod() {
+        let options = ReplayOptions {
+            simulate_live: Some(SimulateLiveOptions::default()),
+            ..Default::default()
+        };
+        let dir = temp_dir("replay-simulate-live");
+        write_live_recording(&dir);
+        // The last snapshot of an ended live recording is not a VOD playlist
+        let ended = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:1\n#EXTINF:4.0,\nsegment-1.ts\n#EXTINF:4.0,\nsegment-2.ts\n#EXT-X-ENDLIST\n";
+        std::fs::write(dir.join("live-1.m3u8"), ended).unwrap();
+        let result = replay_routes(std::slice::from_ref(&dir), options.clone()).await;
+        assert!(matches!(result, Err(ReplayError::SimulateLiveRecording(_))));
+
+        let dir = temp_dir("replay-simulate-vod");
+        std::fs::create_dir_all(&dir).unwrap();
+        let vod = "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-PLAYLIST-TYPE:VOD\n#EXTINF:4.0,\nsegment-0.ts\n#EXT-X-ENDLIST\n";
+        std::fs::write(dir.join("vod.m3u8"), vod).unwrap();
+        let mut recording = Recording::new();
+        recording.add(Utc::now(), "vod.m3u8", "vod.m3u8".to_string());
+        std::fs::write(
+            dir.join("recording.json"),
+            serde_json::to_string(&recording).unwrap(),
+        )
+        .unwrap();
+        let routes = replay_routes(&[dir], options).await.unwrap();
+        let response = warp::test::request()
+            .path(&format!("/vod.m3u8?start={}", started_ago(0)))
+            .reply(&routes)
+            .await;
+        let playlist = body(&response);
+        assert!(playlist.contains("segment-0.ts"), "{playlist}");
+        assert!(!playlist.contains("#EXT-X-ENDLIST"), "{playlist}");
+        assert!(!playlist.contains("#EXT-X-PLAYLIST-TYPE:VOD"), "{playlist}");
+    }
+
+    #[test]
+    fn shift_program_date_times() {
+        let date = |date: &str| DateTime::parse_from_rfc3339(date).unwrap();
+        let mut playlist = MediaPlaylist {
+            segments: vec![MediaSegment {
+                program_date_time: Some(date("2026-09-01T12:00:00Z")),
+                daterange: Some(DateRange {
+                    id: "ad".to_string(),
+                    class: None,
+                    start_date: date("2026-09-01T12:00:02Z"),
+                    end_date: Some(date("2026-09-01T12:00:32Z")),
+                    duration: None,
+                    planned_duration: None,
+                    x_prefixed: None,
+                    end_on_next: false,
+                    other_attributes: None,
+                }),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let recording_start = Utc.with_ymd_and_hms(2026, 9, 1, 11, 59, 0).unwrap();
+        let client_start = Utc.with_ymd_and_hms(2026, 10, 18, 9, 0, 0).unwrap();
+        let offset = replay_offset(recording_start, client_start.timestamp_millis()).unwrap();
+        shift_dates(&mut playlist, offset);
+        let segment = &playlist.segments[0];
+        assert_eq!(
+            segment.program_date_time,
+            Some(date("2026-10-18T09:01:00Z"))
+        );
+        let daterange = segment.daterange.as_ref().unwrap();
+        assert_eq!(daterange.start_date, date("2026-10-18T09:01:02Z"));
+        assert_eq!(daterange.end_date, Some(date("2026-10-18T09:01:32Z")));
+    }
+}
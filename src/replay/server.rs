@@ -0,0 +1,221 @@
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::server::graceful::GracefulShutdown;
+use hyper_util::service::TowerToHyperService;
+use tokio::net::TcpListener;
+use tokio::select;
+use tokio::task::JoinHandle;
+use tokio::time::sleep;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+use warp::http::Method;
+use warp::{Filter, Reply};
+
+use crate::replay::{ReplayError, ReplayOptions, handle_rejection, replay_routes};
+
+/// How long to wait before accepting connections again, after failing to accept one.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(100);
+
+/// Builder for a server that replays one or more recordings in the background.
+///
+/// ```no_run
+/// # use streamrr::replay::ReplayServer;
+/// # async fn example() -> Result<(), streamrr::replay::ReplayError> {
+/// let server = ReplayServer::new(vec!["recording".into()]).port(0).start().await?;
+/// println!("Replaying on {}", server.url());
+/// // ...
+/// server.stop();
+/// server.wait().await;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ReplayServer {
+    recording_paths: Vec<PathBuf>,
+    address: IpAddr,
+    port: u16,
+    options: ReplayOptions,
+}
+
+impl ReplayServer {
+    /// Replay the recordings at `recording_paths`, see [`replay`](crate::replay::replay).
+    ///
+    /// By default, the server listens on `127.0.0.1:8080`.
+    pub fn new(recording_paths: Vec<PathBuf>) -> Self {
+        Self {
+            recording_paths,
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            options: ReplayOptions::default(),
+        }
+    }
+
+    /// The address on which to listen.
+    pub fn address(mut self, address: IpAddr) -> Self {
+        self.address = address;
+        self
+    }
+
+    /// The port on which to listen, or 0 to pick any free port.
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /// How to replay the recordings, e.g. with TLS or a DVR window.
+    ///
+    /// By default, the recordings are served over plain HTTP with [`SessionMode::Query`].
+    ///
+    /// [`SessionMode::Query`]: crate::replay::SessionMode::Query
+    pub fn options(mut self, options: ReplayOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Load the recordings and start serving them in a background task.
+    ///
+    /// Returns once the server is listening.
+    pub async fn start(self) -> Result<ReplayHandle, ReplayError> {
+        let tls_acceptor = self
+            .options
+            .tls
+            .as_ref()
+            .map(|tls| tls.acceptor(self.address))
+            .transpose()?;
+        let routes = replay_routes(&self.recording_paths, self.options).await?;
+
+        let cors = warp::cors().allow_any_origin().build();
+
+        let service = routes.with(cors).recover(handle_rejection);
+
+        let listener = TcpListener::bind(SocketAddr::from((self.address, self.port)))
+            .await
+            .map_err(ReplayError::Bind)?;
+        let local_addr = listener.local_addr().map_err(ReplayError::Bind)?;
+        let token = CancellationToken::new();
+
+        let tls = tls_acceptor.is_some();
+        let task = if let Some(tls_acceptor) = tls_acceptor {
+            info!("Replay server listening on https://{local_addr}/");
+            // Unlike HTTP/1.1, hyper doesn't strip the body of a HEAD response over HTTP/2
+            let service = warp::method().and(service).map(|method: Method, reply| {
+                let mut response = Reply::into_response(reply);
+                if method == Method::HEAD {
+                    *response.body_mut() = Default::default();
+                }
+                response
+            });
+            let service = warp::service(service);
+            let token = token.clone();
+            tokio::spawn(async move {
+                // Keeps track of all open connections, to shut them down gracefully
+                let graceful = GracefulShutdown::new();
+                loop {
+                    let (stream, _) = select! {
+                        connection = listener.accept() => match connection {
+                            Ok(connection) => connection,
+                            Err(e) => {
+                                // E.g. too many open files, so wait for some connections to close
+                                warn!("Failed to accept connection: {e}");
+                                sleep(ACCEPT_ERROR_DELAY).await;
+                                continue;
+                            }
+                        },
+                        _ = token.cancelled() => break,
+                    };
+                    let tls_acceptor = tls_acceptor.clone();
+                    let service = TowerToHyperService::new(service.clone());
+                    let watcher = graceful.watcher();
+                    tokio::spawn(async move {
+                        let stream = match tls_acceptor.accept(stream).await {
+                            Ok(stream) => stream,
+                            Err(e) => {
+                                debug!("TLS handshake failed: {e}");
+                                return;
+                            }
+                        };
+                        // Serve both HTTP/1.1 and HTTP/2, as negotiated with ALPN
+                        let builder = auto::Builder::new(TokioExecutor::new());
+                        let connection = builder.serve_connection(TokioIo::new(stream), service);
+                        if let Err(e) = watcher.watch(connection).await {
+                            debug!("Connection error: {e}");
+                        }
+                    });
+                }
+                drop(listener);
+                graceful.shutdown().await;
+            })
+        } else {
+            info!("Replay server listening on http://{local_addr}/");
+            let server = warp::serve(service)
+                .incoming(listener)
+                .graceful(token.clone().cancelled_owned());
+            tokio::spawn(server.run())
+        };
+
+        Ok(ReplayHandle {
+            local_addr,
+            tls,
+            token,
+            task,
+        })
+    }
+}
+
+/// A handle to a running replay server, returned by [`ReplayServer::start`].
+#[derive(Debug)]
+pub struct ReplayHandle {
+    local_addr: SocketAddr,
+    tls: bool,
+    token: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl ReplayHandle {
+    /// The address on which the server is listening.
+    ///
+    /// This includes the actual port, if the server was started on port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// The base URL of the server, e.g. `http://127.0.0.1:8080/`.
+    ///
+    /// If the server listens on all interfaces (e.g. `0.0.0.0`), this uses `localhost` instead.
+    pub fn url(&self) -> String {
+        let scheme = if self.tls { "https" } else { "http" };
+        if self.local_addr.ip().is_unspecified() {
+            format!("{scheme}://localhost:{}/", self.local_addr.port())
+        } else {
+            format!("{scheme}://{}/", self.local_addr)
+        }
+    }
+
+    /// Stop accepting new connections, and shut down once all open requests are finished.
+    pub fn stop(&self) {
+        self.token.cancel();
+    }
+
+    /// Keep serving until `token` is cancelled, then stop and wait until the server has shut down.
+    ///
+    /// Always returns [`ReplayError::Cancelled`], like [`replay`](crate::replay::replay).
+    pub async fn run_until_cancelled(self, token: CancellationToken) -> Result<(), ReplayError> {
+        token.cancelled().await;
+        self.stop();
+        self.wait().await;
+        Err(ReplayError::Cancelled)
+    }
+
+    /// Wait until the server has shut down after [`stop`](Self::stop).
+    pub async fn wait(self) {
+        if let Err(e) = self.task.await
+            && e.is_panic()
+        {
+            std::panic::resume_unwind(e.into_panic());
+        }
+    }
+}
@@ -0,0 +1,22 @@
+use clap::ValueEnum;
+
+/// The name of the cookie holding the start time of a session.
+pub(crate) const SESSION_COOKIE: &str = "streamrr_start";
+
+/// How the replay server keeps track of the start time of each client.
+///
+/// When replaying a live stream, every client sees the recorded playlists
+/// relative to the start of its session.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum SessionMode {
+    /// Redirect to `?start=<millis>`, and add it to the variant and rendition URIs
+    /// of the master playlist.
+    #[default]
+    Query,
+    /// Redirect to `/s/<millis>/`, so all relative URIs stay in the same session.
+    Path,
+    /// Store the start of the session in a cookie.
+    Cookie,
+    /// Share a single clock between all clients, starting when the server starts.
+    Global,
+}
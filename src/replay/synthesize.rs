@@ -0,0 +1,376 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use m3u8_rs::{ExtTag, MediaPlaylist, MediaPlaylistType, MediaSegment, Playlist};
+use tracing::{debug, warn};
+
+use crate::record::{remove_segments_from_start, strip_media_playlist};
+use crate::replay::{ReplayError, load_playlist};
+
+/// How to replay a VOD recording as a live stream.
+#[derive(Debug, Clone)]
+pub struct SimulateLiveOptions {
+    /// The duration of the live window.
+    ///
+    /// When a client starts, this much of the stream is already available.
+    pub window: Duration,
+    /// Keep all segments since the start of the stream in an EVENT playlist,
+    /// instead of a sliding window.
+    pub event: bool,
+    /// End the playlist with `#EXT-X-ENDLIST` once all segments are available.
+    /// Otherwise, the playlist just stops growing.
+    pub end_list: bool,
+}
+
+impl Default for SimulateLiveOptions {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(30),
+            event: false,
+            end_list: false,
+        }
+    }
+}
+
+/// How to replay a live recording with a DVR window.
+#[derive(Debug, Clone, Default)]
+pub struct DvrOptions {
+    /// The duration of the DVR window,
+    /// or `None` to keep all segments in an EVENT playlist.
+    pub window: Option<Duration>,
+}
+
+/// Turn a VOD media playlist into the live media playlist
+/// at the given position since the start of the client's session.
+pub(crate) fn simulate_live(
+    playlist: &mut MediaPlaylist,
+    options: &SimulateLiveOptions,
+    position: Duration,
+) {
+    // Find all segments that are available at this position
+    let available_until = options.window.as_secs_f64() + position.as_secs_f64();
+    let mut end_time = 0.0;
+    let available_count = playlist
+        .segments
+        .iter()
+        .take_while(|segment| {
+            end_time += f64::from(segment.duration);
+            end_time <= available_until
+        })
+        .count()
+        .max(1);
+    let finished = available_count >= playlist.segments.len();
+    playlist.segments.truncate(available_count);
+    if !options.event {
+        keep_window(playlist, options.window);
+    }
+    playlist.playlist_type = options.event.then_some(MediaPlaylistType::Event);
+    playlist.end_list = finished && options.end_list;
+}
+
+/// Only keep the last segments of a media playlist that fit in the window.
+fn keep_window(playlist: &mut MediaPlaylist, window: Duration) {
+    let window = window.as_secs_f64();
+    let mut window_duration = 0.0;
+    let window_count = playlist
+        .segments
+        .iter()
+        .rev()
+        .take_while(|segment| {
+            window_duration += f64::from(segment.duration);
+            window_duration <= window
+        })
+        .count()
+        .max(1);
+    let remove_count = playlist.segments.len().saturating_sub(window_count);
+    remove_segments_from_start(playlist, playlist.media_sequence + remove_count as u64);
+}
+
+/// All segments of a recorded live media playlist,
+/// along with the time at which they first appeared in the recording.
+#[derive(Debug)]
+pub(crate) struct SegmentHistory {
+    /// The recorded snapshots of the playlist, without their segments.
+    snapshots: BTreeMap<DateTime<Utc>, MediaPlaylist>,
+    /// All segments in order of their media sequence number, starting at `media_sequence`.
+    ///
+    /// Segments that were never recorded are replaced with `#EXT-X-GAP` segments.
+    segments: Vec<HistorySegment>,
+    media_sequence: u64,
+    discontinuity_sequence: u64,
+}
+
+#[derive(Debug)]
+struct HistorySegment {
+    available_at: DateTime<Utc>,
+    segment: MediaSegment,
+}
+
+impl SegmentHistory {
+    /// Collect the segments from all recorded snapshots of a playlist.
+    ///
+    /// Returns `None` if it's not a media playlist.
+    pub(crate) async fn load(
+        recording_path: &Path,
+        snapshots: impl Iterator<Item = (&DateTime<Utc>, &str)>,
+    ) -> Result<Option<Self>, ReplayError> {
+        let mut history: Option<Self> = None;
+        for (time, relative_path) in snapshots {
+            let Playlist::MediaPlaylist(mut playlist) =
+                load_playlist(&recording_path.join(relative_path)).await?
+            else {
+                return Ok(None);
+            };
+            strip_media_playlist(&mut playlist);
+            let segments = std::mem::take(&mut playlist.segments);
+            let history = history.get_or_insert_with(|| Self {
+                snapshots: BTreeMap::new(),
+                segments: Vec::new(),
+                media_sequence: playlist.media_sequence,
+                discontinuity_sequence: playlist.discontinuity_sequence,
+            });
+            let next_media_sequence = history.media_sequence + history.segments.len() as u64;
+            if playlist.media_sequence > next_media_sequence
+                && let Some(next_segment) = segments.first()
+            {
+                warn!(
+                    "Missing segments {next_media_sequence} to {} in {relative_path}",
+                    playlist.media_sequence - 1
+                );
+                // Keep the media sequence numbers of the recorded segments
+                let gap = gap_segment(next_segment, playlist.target_duration);
+                let gap_count = playlist.media_sequence - next_media_sequence;
+                history
+                    .segments
+                    .extend((0..gap_count).map(|_| HistorySegment {
+                        available_at: *time,
+                        segment: gap.clone(),
+                    }));
+            }
+            let new_segments = segments
+                .into_iter()
+                .enumerate()
+                .filter(|(index, _)| playlist.media_sequence + *index as u64 >= next_media_sequence)
+                .map(|(_, segment)| HistorySegment {
+                    available_at: *time,
+                    segment,
+                });
+            history.segments.extend(new_segments);
+            history.snapshots.insert(*time, playlist);
+        }
+        if let Some(history) = &history {
+            debug!(
+                "Loaded history with {} segments from {} snapshots",
+                history.segments.len(),
+                history.snapshots.len()
+            );
+        }
+        Ok(history)
+    }
+
+    /// Synthesize the media playlist with all segments available at the given time
+    /// in the recording.
+    pub(crate) fn playlist_at(
+        &self,
+        time: DateTime<Utc>,
+        options: &DvrOptions,
+    ) -> Option<MediaPlaylist> {
+        let (_, snapshot) = self.snapshots.range(..time).next_back()?;
+        let segments = self
+            .segments
+            .iter()
+            .take_while(|segment| segment.available_at < time)
+            .map(|segment| segment.segment.clone())
+            .collect();
+        let mut playlist = MediaPlaylist {
+            segments,
+            media_sequence: self.media_sequence,
+            discontinuity_sequence: self.discontinuity_sequence,
+            ..snapshot.clone()
+        };
+        match options.window {
+            Some(window) => keep_window(&mut playlist, window),
+            None => playlist.playlist_type = Some(MediaPlaylistType::Event),
+        }
+        Some(playlist)
+    }
+}
+
+/// A placeholder for a segment that is missing from the recording,
+/// which players skip over.
+///
+/// Its duration is unknown, so it uses the target duration.
+fn gap_segment(next_segment: &MediaSegment, target_duration: u64) -> MediaSegment {
+    MediaSegment {
+        // Players must not load the URI of a gap, but it must be a valid URI
+        uri: next_segment.uri.clone(),
+        duration: target_duration as f32,
+        unknown_tags: vec![ExtTag {
+            tag: "X-GAP".to_string(),
+            rest: None,
+        }],
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use m3u8_rs::MediaSegment;
+
+    fn vod_playlist(count: usize) -> MediaPlaylist {
+        let mut segments: Vec<MediaSegment> = (0..count)
+            .map(|index| MediaSegment {
+                uri: format!("segment-{index}.ts"),
+                duration: 4.0,
+                ..Default::default()
+            })
+            .collect();
+        segments[0].program_date_time =
+            Some(DateTime::parse_from_rfc3339("2026-10-18T20:00:00Z").unwrap());
+        segments[3].discontinuity = true;
+        MediaPlaylist {
+            target_duration: 4,
+            segments,
+            playlist_type: Some(MediaPlaylistType::Vod),
+            end_list: true,
+            ..Default::default()
+        }
+    }
+
+    fn uris(playlist: &MediaPlaylist) -> Vec<&str> {
+        playlist.segments.iter().map(|s| s.uri.as_str()).collect()
+    }
+
+    #[test]
+    fn sliding_window() {
+        let options = SimulateLiveOptions {
+            window: Duration::from_secs(12),
+            ..Default::default()
+        };
+        let mut playlist = vod_playlist(6);
+        simulate_live(&mut playlist, &options, Duration::from_secs(5));
+        assert_eq!(
+            uris(&playlist),
+            ["segment-1.ts", "segment-2.ts", "segment-3.ts"]
+        );
+        assert_eq!(playlist.media_sequence, 1);
+        assert_eq!(playlist.discontinuity_sequence, 0);
+        assert_eq!(
+            playlist.segments[0].program_date_time,
+            Some(DateTime::parse_from_rfc3339("2026-10-18T20:00:04Z").unwrap())
+        );
+        assert_eq!(playlist.playlist_type, None);
+        assert!(!playlist.end_list);
+
+        let mut playlist = vod_playlist(6);
+        simulate_live(&mut playlist, &options, Duration::from_secs(60));
+        assert_eq!(
+            uris(&playlist),
+            ["segment-3.ts", "segment-4.ts", "segment-5.ts"]
+        );
+        assert_eq!(playlist.media_sequence, 3);
+        assert!(!playlist.end_list);
+    }
+
+    #[test]
+    fn event_with_end_list() {
+        let options = SimulateLiveOptions {
+            window: Duration::from_secs(8),
+            event: true,
+            end_list: true,
+        };
+        let mut playlist = vod_playlist(6);
+        simulate_live(&mut playlist, &options, Duration::ZERO);
+        assert_eq!(uris(&playlist), ["segment-0.ts", "segment-1.ts"]);
+        assert_eq!(playlist.playlist_type, Some(MediaPlaylistType::Event));
+        assert!(!playlist.end_list);
+
+        let mut playlist = vod_playlist(6);
+        simulate_live(&mut playlist, &options, Duration::from_secs(16));
+        assert_eq!(playlist.segments.len(), 6);
+        assert_eq!(playlist.media_sequence, 0);
+        assert!(playlist.end_list);
+    }
+
+    #[test]
+    fn discontinuity_sequence() {
+        let options = SimulateLiveOptions {
+            window: Duration::from_secs(4),
+            ..Default::default()
+        };
+        let mut playlist = vod_playlist(6);
+        simulate_live(&mut playlist, &options, Duration::from_secs(16));
+        assert_eq!(uris(&playlist), ["segment-4.ts"]);
+        assert_eq!(playlist.discontinuity_sequence, 1);
+    }
+
+    #[tokio::test]
+    async fn history_with_missing_segments() {
+        let dir =
+            std::env::temp_dir().join(format!("streamrr-test-history-gap-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let snapshot = |media_sequence: u64| {
+            let mut playlist = format!(
+                "#EXTM3U\n#EXT-X-TARGETDURATION:4\n#EXT-X-MEDIA-SEQUENCE:{media_sequence}\n"
+            );
+            for index in media_sequence..media_sequence + 2 {
+                playlist.push_str(&format!("#EXTINF:4.0,\nsegment-{index}.ts\n"));
+            }
+            playlist
+        };
+        std::fs::write(dir.join("live-0.m3u8"), snapshot(0)).unwrap();
+        std::fs::write(dir.join("live-1.m3u8"), snapshot(4)).unwrap();
+        let start = DateTime::parse_from_rfc3339("2026-10-18T20:00:00Z")
+            .unwrap()
+            .to_utc();
+        let end = start + Duration::from_secs(8);
+        let snapshots = [(start, "live-0.m3u8"), (end, "live-1.m3u8")];
+        let history =
+            SegmentHistory::load(&dir, snapshots.iter().map(|(time, path)| (time, *path)))
+                .await
+                .unwrap()
+                .unwrap();
+
+        let options = DvrOptions { window: None };
+        let playlist = history
+            .playlist_at(end + Duration::from_secs(1), &options)
+            .unwrap();
+        assert_eq!(playlist.media_sequence, 0);
+        // The missing segments 2 and 3 are gaps, so the later segments keep their numbers
+        assert_eq!(
+            uris(&playlist),
+            [
+                "segment-0.ts",
+                "segment-1.ts",
+                "segment-4.ts",
+                "segment-4.ts",
+                "segment-4.ts",
+                "segment-5.ts"
+            ]
+        );
+        let gaps = playlist
+            .segments
+            .iter()
+            .map(|segment| !segment.unknown_tags.is_empty())
+            .collect::<Vec<_>>();
+        assert_eq!(gaps, [false, false, true, true, false, false]);
+        let mut output = Vec::new();
+        playlist.write_to(&mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.matches("#EXT-X-GAP\n").count(), 2, "{output}");
+
+        // A window after the gap starts at the real media sequence number
+        let options = DvrOptions {
+            window: Some(Duration::from_secs(8)),
+        };
+        let playlist = history
+            .playlist_at(end + Duration::from_secs(1), &options)
+            .unwrap();
+        assert_eq!(playlist.media_sequence, 4);
+        assert_eq!(uris(&playlist), ["segment-4.ts", "segment-5.ts"]);
+    }
+}
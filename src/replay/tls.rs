@@ -0,0 +1,72 @@
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use anyhow::{Context, anyhow};
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::ServerConfig;
+use tokio_rustls::rustls::crypto::aws_lc_rs;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tracing::info;
+
+use crate::replay::ReplayError;
+
+/// How to serve a replay over HTTPS.
+#[derive(Debug, Clone)]
+pub enum TlsOptions {
+    /// Use the certificate chain and private key from these PEM files.
+    Files { cert: PathBuf, key: PathBuf },
+    /// Generate a new self-signed certificate for `localhost` and the server address.
+    SelfSigned,
+}
+
+impl TlsOptions {
+    /// Create a TLS acceptor that supports both HTTP/2 and HTTP/1.1.
+    pub(crate) fn acceptor(&self, address: IpAddr) -> Result<TlsAcceptor, ReplayError> {
+        let (cert_chain, key) = match self {
+            TlsOptions::Files { cert, key } => load_certificate(cert, key),
+            TlsOptions::SelfSigned => generate_certificate(address),
+        }
+        .map_err(ReplayError::Tls)?;
+        let mut config =
+            ServerConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+                .with_safe_default_protocol_versions()
+                .and_then(|builder| {
+                    builder
+                        .with_no_client_auth()
+                        .with_single_cert(cert_chain, key)
+                })
+                .map_err(|e| ReplayError::Tls(anyhow!(e)))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+fn load_certificate(
+    cert: &PathBuf,
+    key: &PathBuf,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let cert_chain = CertificateDer::pem_file_iter(cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Error while reading certificate {}", cert.display()))?;
+    let key = PrivateKeyDer::from_pem_file(key)
+        .with_context(|| format!("Error while reading private key {}", key.display()))?;
+    Ok((cert_chain, key))
+}
+
+fn generate_certificate(
+    address: IpAddr,
+) -> anyhow::Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)> {
+    let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+    if !address.is_unspecified() && !names.contains(&address.to_string()) {
+        names.push(address.to_string());
+    }
+    info!(
+        "Generating self-signed certificate for {}",
+        names.join(", ")
+    );
+    let certified_key = rcgen::generate_simple_self_signed(names)?;
+    let key = PrivatePkcs8KeyDer::from(certified_key.signing_key.serialize_der());
+    Ok((vec![certified_key.cert.der().clone()], key.into()))
+}
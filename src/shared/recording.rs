@@ -1,15 +1,109 @@
 use std::collections::BTreeMap;
 
+use chrono::serde::ts_milliseconds_option;
 use chrono::{DateTime, TimeZone, Utc};
 use clap::ValueEnum;
 use indexmap::IndexMap;
 use m3u8_rs::{AlternativeMedia, VariantStream};
+use serde::de::Error;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
+/// The current version of the `recording.json` format.
+pub const RECORDING_VERSION: u64 = 2;
+
 #[derive(Debug, Default)]
 pub struct Recording {
+    metadata: RecordingMetadata,
     // Playlist file path is keyed by playlist name, then by UTC time
     playlists: IndexMap<String, BTreeMap<DateTime<Utc>, String>>,
+    // Information about each downloaded file, keyed by file path
+    files: BTreeMap<String, FileRecord>,
+}
+
+/// Information about a downloaded playlist, segment, key or map file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileRecord {
+    /// The response headers sent by the origin, in their original order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub headers: Vec<(String, String)>,
+    /// When a segment, key or map file first appeared in a media playlist.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_milliseconds_option"
+    )]
+    pub first_seen: Option<DateTime<Utc>>,
+    /// When the download of the file finished.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "ts_milliseconds_option"
+    )]
+    pub downloaded: Option<DateTime<Utc>>,
+    /// The size of the file in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    /// The time until the response headers were received, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub latency: Option<u64>,
+}
+
+/// Information about how and when a recording was made.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordingMetadata {
+    /// The URL of the recorded stream.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// The version of streamrr that made the recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub streamrr_version: Option<String>,
+    /// The options used to make the recording.
+    #[serde(default, skip_serializing_if = "serde_json::Value::is_null")]
+    pub options: serde_json::Value,
+    /// When the recording started.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub started: Option<DateTime<Utc>>,
+    /// When the recording stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stopped: Option<DateTime<Utc>>,
+    /// Why the recording stopped.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop_reason: Option<StopReason>,
+    /// The error that stopped the recording, if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The machine that made the recording.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<HostInfo>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    /// All playlists ended, either by the stream or by a limit such as `stop_at`.
+    Ended,
+    /// The recording was cancelled, e.g. with Ctrl+C.
+    Cancelled,
+    /// The recording failed with an error.
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+}
+
+impl HostInfo {
+    /// Information about the current machine.
+    pub fn current() -> Self {
+        Self {
+            hostname: gethostname::gethostname().to_string_lossy().to_string(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+        }
+    }
 }
 
 impl Recording {
@@ -17,6 +111,14 @@ impl Recording {
         Self::default()
     }
 
+    pub fn metadata(&self) -> &RecordingMetadata {
+        &self.metadata
+    }
+
+    pub fn metadata_mut(&mut self) -> &mut RecordingMetadata {
+        &mut self.metadata
+    }
+
     pub fn add(&mut self, time: DateTime<Utc>, playlist_name: &str, playlist_path: String) {
         let playlists = if let Some(playlists) = self.playlists.get_mut(playlist_name) {
             playlists
@@ -26,6 +128,27 @@ impl Recording {
         playlists.insert(time, playlist_path);
     }
 
+    pub fn remove(&mut self, playlist_name: &str, time: &DateTime<Utc>) -> Option<String> {
+        self.playlists.get_mut(playlist_name)?.remove(time)
+    }
+
+    /// Add information about a file, with a path relative to the recording.
+    pub fn add_file(&mut self, path: String, file: FileRecord) {
+        self.files.insert(path, file);
+    }
+
+    pub fn remove_file(&mut self, path: &str) -> Option<FileRecord> {
+        self.files.remove(path)
+    }
+
+    pub fn file(&self, path: &str) -> Option<&FileRecord> {
+        self.files.get(path)
+    }
+
+    pub fn playlist_names(&self) -> impl Iterator<Item = &str> {
+        self.playlists.keys().map(String::as_str)
+    }
+
     pub fn earliest_time(&self) -> Option<&DateTime<Utc>> {
         let (time, _path) = self
             .playlists
@@ -35,11 +158,29 @@ impl Recording {
         Some(time)
     }
 
+    pub fn latest_time(&self) -> Option<&DateTime<Utc>> {
+        let (time, _path) = self
+            .playlists
+            .values()
+            .flat_map(|x| x.last_key_value())
+            .max()?;
+        Some(time)
+    }
+
     pub fn earliest_time_for(&self, playlist_name: &str) -> Option<(&DateTime<Utc>, &str)> {
         let (time, path) = self.playlists.get(playlist_name)?.first_key_value()?;
         Some((time, path))
     }
 
+    /// All recorded snapshots of a playlist, in chronological order.
+    pub fn snapshots(&self, playlist_name: &str) -> impl Iterator<Item = (&DateTime<Utc>, &str)> {
+        self.playlists
+            .get(playlist_name)
+            .into_iter()
+            .flatten()
+            .map(|(time, path)| (time, path.as_str()))
+    }
+
     pub fn find_latest_before(
         &self,
         playlist_name: &str,
@@ -54,7 +195,8 @@ impl Recording {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum VariantSelect {
     /// Select the first variant stream.
     #[default]
@@ -67,7 +209,8 @@ pub enum VariantSelect {
     All,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize)]
+#[serde(untagged)]
 pub enum VariantSelectOptions {
     Named(VariantSelect),
     Bandwidth(u64),
@@ -151,7 +294,8 @@ impl VariantSelectOptions {
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum MediaSelect {
     /// Select the default rendition.
     #[default]
@@ -190,7 +334,12 @@ impl MediaSelect {
 
 #[derive(Serialize, Deserialize)]
 pub struct SerializedRecording {
+    version: u64,
+    #[serde(default)]
+    metadata: RecordingMetadata,
     playlists: IndexMap<String, Vec<SerializedPlaylist>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    files: BTreeMap<String, FileRecord>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -205,6 +354,8 @@ impl Serialize for Recording {
         S: Serializer,
     {
         let serialized = SerializedRecording {
+            version: RECORDING_VERSION,
+            metadata: self.metadata.clone(),
             playlists: self
                 .playlists
                 .iter()
@@ -219,6 +370,7 @@ impl Serialize for Recording {
                     (name.clone(), playlists)
                 })
                 .collect(),
+            files: self.files.clone(),
         };
         serialized.serialize(serializer)
     }
@@ -229,21 +381,84 @@ impl<'de> Deserialize<'de> for Recording {
     where
         D: Deserializer<'de>,
     {
-        let serialized = SerializedRecording::deserialize(deserializer)?;
+        let mut value = serde_json::Value::deserialize(deserializer)?;
+        migrate(&mut value).map_err(D::Error::custom)?;
+        let serialized = SerializedRecording::deserialize(value).map_err(D::Error::custom)?;
+        let playlists = serialized
+            .playlists
+            .into_iter()
+            .map(|(name, playlists)| {
+                let playlists = playlists
+                    .into_iter()
+                    .map(|SerializedPlaylist { time, path }| {
+                        let time = Utc.timestamp_millis_opt(time).single().ok_or_else(|| {
+                            D::Error::custom(format!("invalid time {time} for {path}"))
+                        })?;
+                        Ok((time, path))
+                    })
+                    .collect::<Result<_, D::Error>>()?;
+                Ok((name, playlists))
+            })
+            .collect::<Result<_, D::Error>>()?;
         Ok(Recording {
-            playlists: serialized
-                .playlists
-                .into_iter()
-                .map(|(name, playlists)| {
-                    let playlists = playlists
-                        .into_iter()
-                        .map(|SerializedPlaylist { time, path }| {
-                            (Utc.timestamp_millis_opt(time).unwrap(), path)
-                        })
-                        .collect();
-                    (name, playlists)
-                })
-                .collect(),
+            metadata: serialized.metadata,
+            playlists,
+            files: serialized.files,
         })
     }
 }
+
+/// Migrate a serialized recording from an older version of the format to the current version.
+fn migrate(recording: &mut serde_json::Value) -> Result<(), String> {
+    let recording = recording.as_object_mut().ok_or("expected a JSON object")?;
+    // Version 1 had no version number
+    let version = match recording.get("version") {
+        None => 1,
+        Some(version) => version.as_u64().ok_or("invalid version")?,
+    };
+    match version {
+        1 => {
+            // Version 2 added the metadata
+            recording.insert("version".to_string(), RECORDING_VERSION.into());
+        }
+        RECORDING_VERSION => {}
+        _ => {
+            return Err(format!(
+                "unsupported recording version {version}, \
+                 this version of streamrr supports up to version {RECORDING_VERSION}"
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_recording() {
+        let legacy = r#"{"playlists": {"index.m3u8": [{"time": 1000, "path": "index.m3u8"}]}}"#;
+        let recording: Recording = serde_json::from_str(legacy).unwrap();
+        assert_eq!(recording.metadata(), &RecordingMetadata::default());
+        assert_eq!(
+            recording.earliest_time_for("index.m3u8"),
+            Some((&Utc.timestamp_millis_opt(1000).unwrap(), "index.m3u8"))
+        );
+        let serialized = serde_json::to_value(&recording).unwrap();
+        assert_eq!(serialized["version"], RECORDING_VERSION);
+
+        let invalid_time =
+            r#"{"playlists": {"index.m3u8": [{"time": 9223372036854775807, "path": "a.m3u8"}]}}"#;
+        let error = serde_json::from_str::<Recording>(invalid_time).unwrap_err();
+        assert!(error.to_string().contains("invalid time"));
+
+        let future = r#"{"version": 999, "recordings": []}"#;
+        let error = serde_json::from_str::<Recording>(future).unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .contains("unsupported recording version 999")
+        );
+    }
+}
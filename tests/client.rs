@@ -0,0 +1,240 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use hyper_util::server::conn::auto;
+use hyper_util::service::TowerToHyperService;
+use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::crypto::aws_lc_rs;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_util::sync::CancellationToken;
+use url::Url;
+use warp::Filter;
+use warp::filters::BoxedFilter;
+
+use streamrr::record::*;
+
+/// A VOD playlist with two segments, whose segments take `segment_delay` to respond.
+fn vod_routes(segment_delay: Duration) -> BoxedFilter<(String,)> {
+    let playlist = warp::path!("vod.m3u8").map(|| {
+        "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXTINF:1.0,\nsegment-0.ts\n#EXTINF:1.0,\nsegment-1.ts\n#EXT-X-ENDLIST\n"
+            .to_string()
+    });
+    let segment = warp::path!(String).then(move |_| async move {
+        tokio::time::sleep(segment_delay).await;
+        "segment".to_string()
+    });
+    playlist.or(segment).unify().boxed()
+}
+
+/// Serve the routes over plain HTTP on any free port.
+async fn serve(routes: BoxedFilter<(String,)>) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(warp::serve(routes).incoming(listener).run());
+    address
+}
+
+/// A certificate authority, with a server certificate for `localhost` and a client certificate.
+struct TestPki {
+    ca_path: PathBuf,
+    server_chain: Vec<CertificateDer<'static>>,
+    server_key: PrivateKeyDer<'static>,
+    /// The client certificate and its private key, in a single PEM file.
+    client_path: PathBuf,
+    ca_cert: CertificateDer<'static>,
+}
+
+impl TestPki {
+    fn generate(dir: &Path) -> Self {
+        std::fs::create_dir_all(dir).unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(ca_params, KeyPair::generate().unwrap()).unwrap();
+        let ca_path = dir.join("ca.pem");
+        std::fs::write(&ca_path, ca.pem()).unwrap();
+
+        let server_key = KeyPair::generate().unwrap();
+        let server_cert = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&server_key, &ca)
+            .unwrap();
+
+        let client_key = KeyPair::generate().unwrap();
+        let client_cert = CertificateParams::new(vec!["client".to_string()])
+            .unwrap()
+            .signed_by(&client_key, &ca)
+            .unwrap();
+        let client_path = dir.join("client.pem");
+        std::fs::write(
+            &client_path,
+            format!("{}{}", client_cert.pem(), client_key.serialize_pem()),
+        )
+        .unwrap();
+
+        Self {
+            ca_path,
+            server_chain: vec![server_cert.der().clone()],
+            server_key: PrivatePkcs8KeyDer::from(server_key.serialize_der()).into(),
+            client_path,
+            ca_cert: ca.der().clone(),
+        }
+    }
+
+    /// Serve the routes over HTTPS on any free port,
+    /// optionally requiring a client certificate signed by the CA.
+    async fn serve(&self, routes: BoxedFilter<(String,)>, require_client_cert: bool) -> u16 {
+        let provider = Arc::new(aws_lc_rs::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+        let builder = if require_client_cert {
+            let mut roots = RootCertStore::empty();
+            roots.add(self.ca_cert.clone()).unwrap();
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+        let config = builder
+            .with_single_cert(self.server_chain.clone(), self.server_key.clone_key())
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let service = warp::service(routes);
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let acceptor = acceptor.clone();
+                let service = TowerToHyperService::new(service.clone());
+                tokio::spawn(async move {
+                    // Handshakes fail on purpose when the client is not trusted
+                    let Ok(stream) = acceptor.accept(stream).await else {
+                        return;
+                    };
+                    let _ = auto::Builder::new(TokioExecutor::new())
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        port
+    }
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dest = std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dest);
+    dest
+}
+
+/// Record the stream at `url`, and return the number of recorded segments.
+async fn record_segments(
+    url: &Url,
+    name: &str,
+    options: RecordOptions,
+) -> Result<usize, RecordError> {
+    let dest = temp_dir(name);
+    let recording = record(
+        url,
+        &dest,
+        options,
+        RecordProgress::new(),
+        CancellationToken::new(),
+    );
+    timeout(Duration::from_secs(10), recording)
+        .await
+        .expect("recording should stop by itself")?;
+    let segments = std::fs::read_dir(&dest)
+        .unwrap()
+        .filter(|entry| {
+            entry
+                .as_ref()
+                .unwrap()
+                .file_name()
+                .to_string_lossy()
+                .ends_with(".ts")
+        })
+        .count();
+    Ok(segments)
+}
+
+#[tokio::test]
+async fn test_ca_certs() {
+    let pki = TestPki::generate(&temp_dir("ca-certs-pki"));
+    let port = pki.serve(vod_routes(Duration::ZERO), false).await;
+    let url = Url::parse(&format!("https://localhost:{port}/vod.m3u8")).unwrap();
+    // The test CA is not trusted by default
+    let result = record_segments(&url, "ca-certs-untrusted", RecordOptions::default()).await;
+    assert!(result.is_err());
+    let options = RecordOptions {
+        ca_certs: vec![pki.ca_path.clone()],
+        ..Default::default()
+    };
+    assert_eq!(record_segments(&url, "ca-certs", options).await.unwrap(), 2);
+    let options = RecordOptions {
+        insecure: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        record_segments(&url, "ca-certs-insecure", options)
+            .await
+            .unwrap(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_client_cert() {
+    let pki = TestPki::generate(&temp_dir("client-cert-pki"));
+    let port = pki.serve(vod_routes(Duration::ZERO), true).await;
+    let url = Url::parse(&format!("https://localhost:{port}/vod.m3u8")).unwrap();
+    let options = RecordOptions {
+        ca_certs: vec![pki.ca_path.clone()],
+        ..Default::default()
+    };
+    let result = record_segments(&url, "client-cert-missing", options.clone()).await;
+    assert!(result.is_err());
+    let options = RecordOptions {
+        client_cert: Some(pki.client_path.clone()),
+        ..options
+    };
+    assert_eq!(
+        record_segments(&url, "client-cert", options).await.unwrap(),
+        2
+    );
+}
+
+#[tokio::test]
+async fn test_proxy() {
+    // The proxy receives the requests for the unresolvable host, and serves them itself
+    let proxy = serve(vod_routes(Duration::ZERO)).await;
+    let url = Url::parse("http://streamrr.invalid/vod.m3u8").unwrap();
+    let options = RecordOptions {
+        proxy: Some(Url::parse(&format!("http://{proxy}")).unwrap()),
+        ..Default::default()
+    };
+    assert_eq!(record_segments(&url, "proxy", options).await.unwrap(), 2);
+}
+
+#[tokio::test]
+async fn test_read_timeout() {
+    let address = serve(vod_routes(Duration::from_secs(5))).await;
+    let url = Url::parse(&format!("http://{address}/vod.m3u8")).unwrap();
+    let options = RecordOptions {
+        read_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let result = record_segments(&url, "read-timeout", options).await;
+    assert!(matches!(result, Err(RecordError::Io(_))), "{result:?}");
+}
@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+use warp::Filter;
+
+use streamrr::record::*;
+
+const VOD_PLAYLIST: &str = "#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:4
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXT-X-PROGRAM-DATE-TIME:2026-10-18T20:00:00.000Z
+#EXTINF:4.0,
+segment-0.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:4.0,
+segment-1.ts
+#EXTINF:4.0,
+segment-2.ts
+#EXTINF:4.0,
+segment-3.ts
+#EXT-X-ENDLIST
+";
+
+/// Serve [`VOD_PLAYLIST`] and its segments on any free port.
+async fn serve_vod() -> Url {
+    let playlist = warp::path!("vod.m3u8").map(|| VOD_PLAYLIST);
+    let segment = warp::path!(String).map(|_| "segment");
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(warp::serve(playlist.or(segment)).incoming(listener).run());
+    Url::parse(&format!("http://{address}/vod.m3u8")).unwrap()
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dest = std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dest);
+    dest
+}
+
+#[tokio::test]
+async fn test_start_after_discontinuity() {
+    let url = serve_vod().await;
+    let dest = temp_dir("start-after-discontinuity");
+    let options = RecordOptions {
+        start: Some(PlaylistPosition::Offset(8.0)),
+        ..Default::default()
+    };
+    let recording = record(
+        &url,
+        &dest,
+        options,
+        RecordProgress::new(),
+        CancellationToken::new(),
+    );
+    timeout(Duration::from_secs(10), recording)
+        .await
+        .expect("recording should stop by itself")
+        .unwrap();
+    let playlist = std::fs::read_dir(&dest)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.extension().is_some_and(|ext| ext == "m3u8"))
+        .unwrap();
+    let playlist = std::fs::read_to_string(playlist).unwrap();
+    // The removed discontinuity is counted, and the first recorded segment keeps its time
+    assert!(playlist.contains("#EXT-X-MEDIA-SEQUENCE:2\n"), "{playlist}");
+    assert!(
+        playlist.contains("#EXT-X-DISCONTINUITY-SEQUENCE:1\n"),
+        "{playlist}"
+    );
+    assert!(
+        playlist.contains("#EXT-X-PROGRAM-DATE-TIME:2026-10-18T20:00:08.000Z\n"),
+        "{playlist}"
+    );
+    assert!(!playlist.contains("segment-1"), "{playlist}");
+}
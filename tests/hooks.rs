@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+use streamrr::record::*;
+
+/// Serve a VOD playlist with two segments, and a webhook that collects all events.
+///
+/// The webhook at `/fail` always fails.
+async fn serve_vod_and_webhook() -> (SocketAddr, Arc<Mutex<Vec<serde_json::Value>>>) {
+    let playlist = warp::path!("vod.m3u8").map(|| {
+        "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXTINF:1.0,\nsegment-0.ts\n#EXTINF:1.0,\nsegment-1.ts\n#EXT-X-ENDLIST\n"
+            .into_response()
+    });
+    let received = Arc::new(Mutex::new(Vec::new()));
+    let webhook = {
+        let received = received.clone();
+        warp::path!("webhook")
+            .and(warp::post())
+            .and(warp::header::exact("content-type", "application/json"))
+            .and(warp::body::json())
+            .map(move |event: serde_json::Value| {
+                received.lock().unwrap().push(event);
+                StatusCode::NO_CONTENT.into_response()
+            })
+    };
+    let fail = warp::path!("fail")
+        .and(warp::post())
+        .map(|| StatusCode::INTERNAL_SERVER_ERROR.into_response());
+    let segment = warp::path!(String).map(|_| "segment".into_response());
+    let routes = playlist
+        .or(webhook)
+        .unify()
+        .or(fail)
+        .unify()
+        .or(segment)
+        .unify();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(warp::serve(routes).incoming(listener).run());
+    (address, received)
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dest = std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dest);
+    dest
+}
+
+/// Record the stream at `url` with the given hooks, until it stops by itself.
+async fn record_with_hooks(url: &Url, dest: &Path, event_hooks: Vec<EventHook>) {
+    let options = RecordOptions {
+        event_hooks,
+        ..Default::default()
+    };
+    let recording = record(
+        url,
+        dest,
+        options,
+        RecordProgress::new(),
+        CancellationToken::new(),
+    );
+    timeout(Duration::from_secs(10), recording)
+        .await
+        .expect("recording should stop by itself")
+        .unwrap();
+}
+
+/// The types of the events, in order.
+fn event_types(events: &[serde_json::Value]) -> Vec<&str> {
+    events
+        .iter()
+        .map(|event| event["type"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn test_webhook() {
+    let (address, received) = serve_vod_and_webhook().await;
+    let url = Url::parse(&format!("http://{address}/vod.m3u8")).unwrap();
+    let hooks = vec![
+        // A failing webhook does not stop the recording, nor the other hooks
+        EventHook::Webhook(Url::parse(&format!("http://{address}/fail")).unwrap()),
+        EventHook::Webhook(Url::parse(&format!("http://{address}/webhook")).unwrap()),
+    ];
+    record_with_hooks(&url, &temp_dir("webhook"), hooks).await;
+    // All events are delivered before the recording stops
+    let received = received.lock().unwrap();
+    let mut types = event_types(&received);
+    // Segments are downloaded concurrently
+    types[1..3].sort();
+    assert_eq!(
+        types,
+        [
+            "playlist_fetched",
+            "segment_downloaded",
+            "segment_downloaded",
+            "playlist_ended",
+            "stopped"
+        ]
+    );
+    assert_eq!(received[0]["playlist"], "index.m3u8");
+    assert_eq!(received[0]["segments"], 2);
+    assert_eq!(received[4]["reason"], "ended");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_command_hook() {
+    let (address, _) = serve_vod_and_webhook().await;
+    let url = Url::parse(&format!("http://{address}/vod.m3u8")).unwrap();
+    let dest = temp_dir("command-hook");
+    let output = temp_dir("command-hook-output");
+    std::fs::create_dir_all(&output).unwrap();
+    let events_path = output.join("events.jsonl");
+    let types_path = output.join("types.txt");
+    let hooks = vec![
+        EventHook::Command("exit 1".to_string()),
+        EventHook::Command(format!(
+            "cat >> '{}' && echo \"$STREAMRR_EVENT\" >> '{}'",
+            events_path.display(),
+            types_path.display()
+        )),
+    ];
+    record_with_hooks(&url, &dest, hooks).await;
+    // Every event is passed as a line of JSON on stdin
+    let events = std::fs::read_to_string(&events_path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .collect::<Vec<_>>();
+    let types = std::fs::read_to_string(&types_path).unwrap();
+    assert_eq!(types.lines().collect::<Vec<_>>(), event_types(&events));
+    assert_eq!(events.len(), 5);
+    assert_eq!(events[0]["type"], "playlist_fetched");
+    assert_eq!(events[4]["type"], "stopped");
+}
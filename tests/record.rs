@@ -0,0 +1,508 @@
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use tokio_util::sync::CancellationToken;
+use url::Url;
+use warp::http::StatusCode;
+use warp::{Filter, Reply};
+
+use streamrr::record::*;
+use streamrr::shared::StopReason;
+
+const SEGMENT_SIZE: usize = 100;
+
+/// Serve a live stream that adds a new segment on every playlist request,
+/// keeping at most `window` segments in the playlist.
+async fn serve_live_stream(initial_segments: u64, window: u64) -> Url {
+    let requests = Arc::new(AtomicU64::new(0));
+    let playlist = warp::path!("live.m3u8").map(move || {
+        let count = initial_segments + requests.fetch_add(1, Ordering::SeqCst);
+        let first = count.saturating_sub(window);
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{first}\n"
+        );
+        for index in first..count {
+            playlist.push_str(&format!("#EXTINF:1.0,\nsegment-{index}.ts\n"));
+        }
+        playlist
+    });
+    let segment = warp::path!(String).map(|_| "x".repeat(SEGMENT_SIZE));
+    let address = serve(playlist.or(segment)).await;
+    Url::parse(&format!("http://{address}/live.m3u8")).unwrap()
+}
+
+/// Serve the routes on any free port.
+async fn serve<F>(routes: F) -> SocketAddr
+where
+    F: Filter + Clone + Send + Sync + 'static,
+    F::Extract: Reply,
+{
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(warp::serve(routes).incoming(listener).run());
+    address
+}
+
+/// The number of concurrent requests to a server.
+#[derive(Default)]
+struct Concurrency {
+    current: AtomicU64,
+    max: AtomicU64,
+}
+
+/// Serve a VOD playlist with slow segments, alternating between the given host names
+/// for the same server, and track the number of concurrent segment requests.
+async fn serve_slow_vod(hosts: &[&str]) -> (Url, Arc<Concurrency>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let mut playlist = "#EXTM3U\n#EXT-X-TARGETDURATION:1\n".to_string();
+    for index in 0..8 {
+        let host = hosts[index % hosts.len()];
+        playlist.push_str(&format!(
+            "#EXTINF:1.0,\nhttp://{host}:{port}/segment-{index}.ts\n"
+        ));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    let playlist = warp::path!("vod.m3u8").map(move || playlist.clone());
+    let concurrency = Arc::new(Concurrency::default());
+    let segment = {
+        let concurrency = concurrency.clone();
+        warp::path!(String).then(move |_| {
+            let concurrency = concurrency.clone();
+            async move {
+                let current = concurrency.current.fetch_add(1, Ordering::SeqCst) + 1;
+                concurrency.max.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                concurrency.current.fetch_sub(1, Ordering::SeqCst);
+                "x".repeat(SEGMENT_SIZE)
+            }
+        })
+    };
+    tokio::spawn(warp::serve(playlist.or(segment)).incoming(listener).run());
+    let url = Url::parse(&format!("http://127.0.0.1:{port}/vod.m3u8")).unwrap();
+    (url, concurrency)
+}
+
+/// Record a stream until it stops by itself.
+async fn record_until_end(url: &Url, name: &str, options: RecordOptions) -> PathBuf {
+    let dest = temp_dir(name);
+    let recording = record(
+        url,
+        &dest,
+        options,
+        RecordProgress::new(),
+        CancellationToken::new(),
+    );
+    timeout(Duration::from_secs(10), recording)
+        .await
+        .expect("recording should stop by itself")
+        .unwrap();
+    dest
+}
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dest = std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dest);
+    dest
+}
+
+/// Record a live stream until it stops by itself.
+async fn record_live_stream(
+    name: &str,
+    initial_segments: u64,
+    window: u64,
+    options: RecordOptions,
+) -> PathBuf {
+    let url = serve_live_stream(initial_segments, window).await;
+    let dest = temp_dir(name);
+    let options = RecordOptions {
+        poll_interval: Some(Duration::from_millis(50)),
+        ..options
+    };
+    let recording = record(
+        &url,
+        &dest,
+        options,
+        RecordProgress::new(),
+        CancellationToken::new(),
+    );
+    timeout(Duration::from_secs(10), recording)
+        .await
+        .expect("recording should stop by itself")
+        .unwrap();
+    dest
+}
+
+/// The names of all recorded segments, in order.
+fn recorded_segments(dest: &Path) -> Vec<String> {
+    let mut segments = std::fs::read_dir(dest)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+        .filter(|name| name.ends_with(".ts"))
+        .collect::<Vec<_>>();
+    segments.sort_by_key(|name| (name.len(), name.clone()));
+    segments
+}
+
+/// The contents of the last recorded snapshot of the playlist.
+fn last_snapshot(dest: &Path) -> String {
+    let last = std::fs::read_dir(dest)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "m3u8"))
+        .max()
+        .unwrap();
+    std::fs::read_to_string(last).unwrap()
+}
+
+#[tokio::test]
+async fn test_max_segments_across_refreshes() {
+    let options = RecordOptions {
+        max_segments: Some(5),
+        ..Default::default()
+    };
+    let dest = record_live_stream("max-segments-refresh", 3, 3, options).await;
+    // The recording is saved once more when it stops
+    let recording: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dest.join("recording.json")).unwrap())
+            .unwrap();
+    assert!(recording["files"]["segment-4.ts"].is_object());
+    assert_eq!(recording["metadata"]["stop_reason"], "ended");
+    assert_eq!(
+        recorded_segments(&dest),
+        [
+            "segment-0.ts",
+            "segment-1.ts",
+            "segment-2.ts",
+            "segment-3.ts",
+            "segment-4.ts"
+        ]
+    );
+    assert!(last_snapshot(&dest).contains("#EXT-X-ENDLIST"));
+}
+
+#[tokio::test]
+async fn test_max_segments_in_first_playlist() {
+    let options = RecordOptions {
+        max_segments: Some(2),
+        ..Default::default()
+    };
+    let dest = record_live_stream("max-segments-first", 6, 6, options).await;
+    assert_eq!(recorded_segments(&dest), ["segment-0.ts", "segment-1.ts"]);
+    assert!(last_snapshot(&dest).contains("#EXT-X-ENDLIST"));
+}
+
+#[tokio::test]
+async fn test_max_size_across_refreshes() {
+    let options = RecordOptions {
+        max_size: Some(450),
+        ..Default::default()
+    };
+    let dest = record_live_stream("max-size-refresh", 3, 3, options).await;
+    // Stops at the first refresh after the size was reached
+    assert_eq!(recorded_segments(&dest).len(), 5);
+    assert!(last_snapshot(&dest).contains("#EXT-X-ENDLIST"));
+}
+
+#[tokio::test]
+async fn test_max_size_in_first_playlist() {
+    let options = RecordOptions {
+        max_size: Some(150),
+        // Download one segment at a time, so no more segments are started once the size is reached
+        concurrent_downloads: NonZeroUsize::new(1),
+        ..Default::default()
+    };
+    let dest = record_live_stream("max-size-first", 6, 6, options).await;
+    // Stops after the segment that reached the size, without waiting for a refresh
+    assert_eq!(recorded_segments(&dest), ["segment-0.ts", "segment-1.ts"]);
+    let snapshot = last_snapshot(&dest);
+    assert!(snapshot.contains("#EXT-X-ENDLIST"));
+    assert!(!snapshot.contains("segment-2"));
+}
+
+#[tokio::test]
+async fn test_failed_segment_download() {
+    let playlist = warp::path!("vod.m3u8")
+        .map(|| "#EXTM3U\n#EXT-X-TARGETDURATION:1\n#EXTINF:1.0,\nsegment-0.ts\n#EXT-X-ENDLIST\n");
+    let segment = warp::path!(String)
+        .map(|_| warp::reply::with_status("Not Found", StatusCode::NOT_FOUND).into_response());
+    let address = serve(playlist.or(segment)).await;
+    let url = Url::parse(&format!("http://{address}/vod.m3u8")).unwrap();
+    let dest = temp_dir("failed-segment");
+    let result = record(
+        &url,
+        &dest,
+        RecordOptions::default(),
+        RecordProgress::new(),
+        CancellationToken::new(),
+    )
+    .await;
+    assert!(matches!(
+        result,
+        Err(RecordError::Http {
+            status: StatusCode::NOT_FOUND,
+            ..
+        })
+    ));
+    // The error response is not kept as the segment
+    assert_eq!(recorded_segments(&dest), Vec::<String>::new());
+}
+
+#[tokio::test]
+async fn test_progress_lag() {
+    // Every refresh adds one segment of 1 second
+    let url = serve_live_stream(3, 3).await;
+    let dest = temp_dir("progress-lag");
+    let options = RecordOptions {
+        poll_interval: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let progress = RecordProgress::new();
+    let token = CancellationToken::new();
+    let recording = record(&url, &dest, options, progress.clone(), token.clone());
+    let check = async {
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let total = progress.total();
+        token.cancel();
+        total
+    };
+    let (result, total) = tokio::join!(recording, check);
+    assert!(matches!(result, Err(RecordError::Cancelled)));
+    // Measured when the playlist was fetched, not reset after downloading
+    assert!(total.playlists > 1);
+    assert_eq!(total.lag, Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn test_max_connections_per_host() {
+    let (url, concurrency) = serve_slow_vod(&["127.0.0.1", "localhost"]).await;
+    let options = RecordOptions {
+        concurrent_downloads: NonZeroUsize::new(8),
+        max_connections_per_host: NonZeroUsize::new(2),
+        ..Default::default()
+    };
+    let dest = record_until_end(&url, "max-connections-per-host", options).await;
+    assert_eq!(recorded_segments(&dest).len(), 8);
+    // Two connections to each of the two hosts
+    assert_eq!(concurrency.max.load(Ordering::SeqCst), 4);
+}
+
+#[tokio::test]
+async fn test_max_connections() {
+    let (url, concurrency) = serve_slow_vod(&["127.0.0.1", "localhost"]).await;
+    let options = RecordOptions {
+        concurrent_downloads: NonZeroUsize::new(8),
+        max_connections: NonZeroUsize::new(3),
+        ..Default::default()
+    };
+    let dest = record_until_end(&url, "max-connections", options).await;
+    assert_eq!(recorded_segments(&dest).len(), 8);
+    assert_eq!(concurrency.max.load(Ordering::SeqCst), 3);
+}
+
+/// Receive events until the recording stops, and return them all.
+async fn collect_events(
+    events: &mut tokio::sync::broadcast::Receiver<RecordEvent>,
+) -> Vec<RecordEvent> {
+    let mut collected = Vec::new();
+    loop {
+        let event = timeout(Duration::from_secs(10), events.recv())
+            .await
+            .expect("recording should stop")
+            .unwrap();
+        let stopped = matches!(event, RecordEvent::Stopped { .. });
+        collected.push(event);
+        if stopped {
+            return collected;
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_recorder_events() {
+    let url = serve_live_stream(2, 2).await;
+    let dest = temp_dir("recorder-events");
+    let options = RecordOptions {
+        max_segments: Some(3),
+        poll_interval: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let recorder = Recorder::new(url, &dest).options(options);
+    let mut events = recorder.subscribe();
+    let handle = recorder.start();
+    let events = collect_events(&mut events).await;
+    handle.wait().await.unwrap();
+
+    let fetched = events
+        .iter()
+        .filter(|event| matches!(event, RecordEvent::PlaylistFetched { .. }))
+        .count();
+    assert!(fetched >= 2, "{events:?}");
+    let mut downloaded = events
+        .iter()
+        .filter_map(|event| match event {
+            RecordEvent::SegmentDownloaded {
+                file_name, size, ..
+            } => Some((file_name.as_str(), *size)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // Segments are downloaded concurrently, so in any order
+    downloaded.sort();
+    assert_eq!(
+        downloaded,
+        [
+            ("segment-0.ts", SEGMENT_SIZE as u64),
+            ("segment-1.ts", SEGMENT_SIZE as u64),
+            ("segment-2.ts", SEGMENT_SIZE as u64)
+        ]
+    );
+    let [.., ended, stopped] = events.as_slice() else {
+        panic!("{events:?}");
+    };
+    assert!(
+        matches!(ended, RecordEvent::PlaylistEnded { .. }),
+        "{events:?}"
+    );
+    assert_eq!(
+        *stopped,
+        RecordEvent::Stopped {
+            reason: StopReason::Ended
+        }
+    );
+    assert_eq!(recorded_segments(&dest).len(), 3);
+}
+
+#[tokio::test]
+async fn test_recorder_stop() {
+    let url = serve_live_stream(2, 2).await;
+    let dest = temp_dir("recorder-stop");
+    let options = RecordOptions {
+        poll_interval: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let handle = Recorder::new(url, &dest).options(options).start();
+    let mut events = handle.subscribe();
+    // Stop after the first downloaded segment
+    loop {
+        let event = timeout(Duration::from_secs(10), events.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        if matches!(event, RecordEvent::SegmentDownloaded { .. }) {
+            break;
+        }
+    }
+    assert!(!handle.is_finished());
+    handle.stop();
+    let events = collect_events(&mut events).await;
+    assert_eq!(
+        events.last(),
+        Some(&RecordEvent::Stopped {
+            reason: StopReason::Cancelled
+        })
+    );
+    let result = timeout(Duration::from_secs(10), handle.wait())
+        .await
+        .unwrap();
+    assert!(matches!(result, Err(RecordError::Cancelled)), "{result:?}");
+    // The recording is finalized
+    let recording: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(dest.join("recording.json")).unwrap())
+            .unwrap();
+    assert_eq!(recording["metadata"]["stop_reason"], "cancelled");
+    assert!(!recorded_segments(&dest).is_empty());
+}
+
+/// Serve a live stream like [`serve_live_stream`], whose playlist returns 404
+/// for the requests where `gone` returns true, and count all playlist requests.
+async fn serve_disappearing_stream(gone: fn(u64) -> bool) -> (Url, Arc<AtomicU64>) {
+    let requests = Arc::new(AtomicU64::new(0));
+    let playlist = {
+        let requests = requests.clone();
+        warp::path!("live.m3u8").map(move || {
+            let request = requests.fetch_add(1, Ordering::SeqCst);
+            if gone(request) {
+                return warp::reply::with_status("Not Found", StatusCode::NOT_FOUND)
+                    .into_response();
+            }
+            let count = 2 + request;
+            let first = count - 2;
+            let mut playlist = format!(
+                "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:1\n#EXT-X-MEDIA-SEQUENCE:{first}\n"
+            );
+            for index in first..count {
+                playlist.push_str(&format!("#EXTINF:1.0,\nsegment-{index}.ts\n"));
+            }
+            playlist.into_response()
+        })
+    };
+    let segment = warp::path!(String).map(|_| "x".repeat(SEGMENT_SIZE));
+    let address = serve(playlist.or(segment)).await;
+    let url = Url::parse(&format!("http://{address}/live.m3u8")).unwrap();
+    (url, requests)
+}
+
+#[tokio::test]
+async fn test_playlist_gone() {
+    let (url, requests) = serve_disappearing_stream(|request| request >= 2).await;
+    let options = RecordOptions {
+        poll_interval: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let recorder = Recorder::new(url, temp_dir("playlist-gone")).options(options);
+    let mut events = recorder.subscribe();
+    let handle = recorder.start();
+    let events = collect_events(&mut events).await;
+    let result = handle.wait().await;
+    assert!(
+        matches!(
+            result,
+            Err(RecordError::Http {
+                status: StatusCode::NOT_FOUND,
+                ..
+            })
+        ),
+        "{result:?}"
+    );
+    // The playlist is retried 3 times before giving up
+    assert_eq!(requests.load(Ordering::SeqCst), 6);
+    let gone = events
+        .iter()
+        .filter(|event| matches!(event, RecordEvent::PlaylistGone { status: 404, .. }))
+        .count();
+    assert_eq!(gone, 1, "{events:?}");
+    assert_eq!(
+        events.last(),
+        Some(&RecordEvent::Stopped {
+            reason: StopReason::Failed
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_playlist_temporarily_gone() {
+    let (url, _) = serve_disappearing_stream(|request| request == 2 || request == 3).await;
+    let options = RecordOptions {
+        max_segments: Some(4),
+        poll_interval: Some(Duration::from_millis(50)),
+        ..Default::default()
+    };
+    let recorder = Recorder::new(url, temp_dir("playlist-temporarily-gone")).options(options);
+    let mut events = recorder.subscribe();
+    let handle = recorder.start();
+    let events = collect_events(&mut events).await;
+    handle.wait().await.unwrap();
+    assert!(
+        !events
+            .iter()
+            .any(|event| matches!(event, RecordEvent::PlaylistGone { .. })),
+        "{events:?}"
+    );
+}
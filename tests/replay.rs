@@ -0,0 +1,90 @@
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use reqwest::{Client, StatusCode, Version};
+
+use streamrr::replay::*;
+use streamrr::shared::Recording;
+
+const VOD_PLAYLIST: &str = "#EXTM3U
+#EXT-X-TARGETDURATION:4
+#EXT-X-PLAYLIST-TYPE:VOD
+#EXTINF:4.0,
+segment-0.ts
+#EXT-X-ENDLIST
+";
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dest = std::env::temp_dir().join(format!("streamrr-test-{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dest);
+    dest
+}
+
+/// Write a recording of [`VOD_PLAYLIST`] and its segment.
+fn write_vod_recording(dir: &Path) {
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join("vod.m3u8"), VOD_PLAYLIST).unwrap();
+    std::fs::write(dir.join("segment-0.ts"), "segment").unwrap();
+    let mut recording = Recording::new();
+    recording.add(Utc::now(), "vod.m3u8", "vod.m3u8".to_string());
+    std::fs::write(
+        dir.join("recording.json"),
+        serde_json::to_string(&recording).unwrap(),
+    )
+    .unwrap();
+}
+
+#[tokio::test]
+async fn test_tls() {
+    let dir = temp_dir("replay-tls");
+    write_vod_recording(&dir);
+    let options = ReplayOptions {
+        tls: Some(TlsOptions::SelfSigned),
+        ..Default::default()
+    };
+    let server = ReplayServer::new(vec![dir])
+        .port(0)
+        .options(options)
+        .start()
+        .await
+        .unwrap();
+    let url = server.url();
+    assert!(url.starts_with("https://127.0.0.1:"), "{url}");
+
+    // HTTP/2 is negotiated with ALPN, and HTTP/1.1 is still supported
+    let http2 = Client::builder()
+        .tls_danger_accept_invalid_certs(true)
+        .build()
+        .unwrap();
+    let http1 = Client::builder()
+        .tls_danger_accept_invalid_certs(true)
+        .http1_only()
+        .build()
+        .unwrap();
+    for (client, version) in [(http2, Version::HTTP_2), (http1, Version::HTTP_11)] {
+        let response = client
+            .get(format!("{url}vod.m3u8?start=0"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), version);
+        assert!(response.text().await.unwrap().contains("segment-0.ts"));
+
+        let response = client
+            .head(format!("{url}segment-0.ts"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.version(), version);
+        assert_eq!(response.bytes().await.unwrap().len(), 0);
+    }
+
+    // Plain HTTP requests are rejected
+    let plain = format!("http://{}/vod.m3u8", server.local_addr());
+    assert!(Client::new().get(plain).send().await.is_err());
+
+    server.stop();
+    server.wait().await;
+}
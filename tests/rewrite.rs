@@ -1,3 +1,4 @@
+use chrono::{DateTime, Utc};
 use insta::*;
 use m3u8_rs::*;
 use streamrr::record::*;
@@ -32,6 +33,23 @@ fn test_rewrite_elephants_dream() {
     assert_snapshot!(media_playlist_to_string(&playlist));
 }
 
+#[test]
+fn test_playlist_path_with_timestamp() {
+    let url = Url::parse("https://example.com/live/index.m3u8").unwrap();
+    let rewriter = Rewriter::new(&url, "live".as_ref(), false);
+    let first = "2026-10-18T20:00:00.100Z".parse::<DateTime<Utc>>().unwrap();
+    let second = "2026-10-18T20:00:00.600Z".parse::<DateTime<Utc>>().unwrap();
+    assert_eq!(
+        rewriter.playlist_path_with_timestamp(&first),
+        "live/index-20261018T200000100.m3u8"
+    );
+    // Snapshots in the same second must not overwrite each other
+    assert_ne!(
+        rewriter.playlist_path_with_timestamp(&first),
+        rewriter.playlist_path_with_timestamp(&second)
+    );
+}
+
 fn master_playlist_to_string(playlist: &MasterPlaylist) -> String {
     let mut buffer = vec![];
     playlist.write_to(&mut buffer).unwrap();
@@ -43,3 +61,67 @@ fn media_playlist_to_string(playlist: &MediaPlaylist) -> String {
     playlist.write_to(&mut buffer).unwrap();
     String::from_utf8(buffer).unwrap()
 }
+
+#[test]
+fn test_remove_segments_from_start() {
+    let playlist = br#"#EXTM3U
+#EXT-X-VERSION:3
+#EXT-X-TARGETDURATION:4
+#EXT-X-MEDIA-SEQUENCE:10
+#EXT-X-DISCONTINUITY-SEQUENCE:2
+#EXT-X-PROGRAM-DATE-TIME:2026-10-18T20:00:00.000Z
+#EXTINF:4.0,
+segment-10.ts
+#EXT-X-DISCONTINUITY
+#EXTINF:3.5,
+segment-11.ts
+#EXTINF:4.0,
+segment-12.ts
+#EXT-X-DISCONTINUITY
+#EXT-X-PROGRAM-DATE-TIME:2026-10-18T21:00:00.000Z
+#EXTINF:4.0,
+segment-13.ts
+#EXTINF:4.0,
+segment-14.ts
+"#;
+    let playlist = parse_media_playlist_res(playlist).unwrap();
+
+    // Across a discontinuity, without a program date time on the new first segment
+    let mut removed = playlist.clone();
+    remove_segments_from_start(&mut removed, 12);
+    assert_eq!(removed.media_sequence, 12);
+    assert_eq!(removed.discontinuity_sequence, 3);
+    assert_eq!(removed.segments.len(), 3);
+    assert_eq!(removed.segments[0].uri, "segment-12.ts");
+    assert_eq!(
+        removed.segments[0].program_date_time,
+        Some("2026-10-18T20:00:07.500Z".parse().unwrap())
+    );
+
+    // Up to a segment with its own discontinuity and program date time
+    let mut removed = playlist.clone();
+    remove_segments_from_start(&mut removed, 13);
+    assert_eq!(removed.media_sequence, 13);
+    assert_eq!(removed.discontinuity_sequence, 3);
+    assert!(removed.segments[0].discontinuity);
+    assert_eq!(
+        removed.segments[0].program_date_time,
+        Some("2026-10-18T21:00:00.000Z".parse().unwrap())
+    );
+
+    // Across both discontinuities, using the last program date time
+    let mut removed = playlist.clone();
+    remove_segments_from_start(&mut removed, 14);
+    assert_eq!(removed.media_sequence, 14);
+    assert_eq!(removed.discontinuity_sequence, 4);
+    assert_eq!(removed.segments.len(), 1);
+    assert_eq!(
+        removed.segments[0].program_date_time,
+        Some("2026-10-18T21:00:04.000Z".parse().unwrap())
+    );
+
+    // Nothing to remove
+    let mut removed = playlist.clone();
+    remove_segments_from_start(&mut removed, 10);
+    assert_eq!(removed, playlist);
+}